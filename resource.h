@@ -7,15 +7,66 @@
 #define IDC_LITEX_BOOT_ADDR                 1003
 #define IDC_LITEX_CHOOSE_KERNEL_BUTTON      1004
 #define IDC_LITEX_ACTIVE                    1005
+#define IDC_LITEX_PROTOCOL                  1006
+#define IDC_LITEX_MAGIC                     1007
+#define IDC_LITEX_MAGIC_RESPONSE            1008
+#define IDC_LITEX_MAX_PAYLOAD               1009
+#define IDC_LITEX_ALLOW_JUMP                1010
+#define IDC_LITEX_JUMP_ADDR                 1011
+#define IDC_LITEX_BOARD                     1012
+#define IDC_LITEX_BOARD_NEW                 1013
+#define IDC_LITEX_BOARD_RENAME              1014
+#define IDC_LITEX_BOARD_DELETE              1015
+#define IDC_LITEX_BOARD_NAME                1017
+#define IDC_LITEX_PROJECT_CONFIG            1018
+#define IDC_LITEX_KERNEL_ERR                1019
+#define IDC_LITEX_BOOT_ADDR_ERR             1020
+#define IDC_LITEX_JUMP_ADDR_ERR             1021
+#define IDC_LITEX_PROTOCOL_ERR              1022
+#define IDC_LITEX_SUMMARY                   1023
+#define IDC_LITEX_CHUNK_AUTO                1024
+#define IDC_LITEX_CHUNK_SIZE                1025
+#define IDC_LITEX_MAX_IN_FLIGHT             1026
+#define IDC_LITEX_FRAME_TIMEOUT             1027
+#define IDC_LITEX_MAX_RETRIES               1028
+#define IDC_LITEX_STATUS_BAR                1029
+#define IDC_LITEX_ADVANCED_ERR              1030
+#define IDC_LITEX_TRANSFER_STATUS           1031
+#define IDC_LITEX_PROGRESS_WINDOW           1032
+#define IDC_LITEX_PROGRESS_BAR              1034
+#define IDC_LITEX_PROGRESS_TEXT             1035
+#define IDC_LITEX_STATUS_COMPACT            1036
+#define IDC_LITEX_DETAILED_REPORT           1037
+#define IDC_LITEX_MESSAGE_LEVEL             1038
+#define IDC_LITEX_MESSAGE_COLOR             1039
+#define IDC_LITEX_CSR_JSON                  1040
+#define IDC_LITEX_CHOOSE_CSR_BUTTON         1041
+#define IDC_LITEX_CSR_ERR                   1042
+#define IDC_LITEX_REGION                    1043
+#define IDC_LITEX_DETECT_BOOT_ADDR          1044
+#define IDC_LITEX_TRIGGER                   1045
+#define IDC_LITEX_TRIGGER_TIMEOUT           1046
+#define IDC_LITEX_WATCH_FILE                1047
+#define IDC_LITEX_ARM_MODE                  1048
+#define IDC_LITEX_ARM_COUNT                 1049
+#define IDC_LITEX_ARM_EXPIRY                1050
+#define IDC_LITEX_CONFIRM_UPLOAD            1051
+#define IDC_LITEX_RESTART_ON_RESET          1052
+#define IDC_LITEX_RESUME_UPLOADS            1053
+#define IDC_LITEX_BOOT_PATTERN              1054
+#define IDC_LITEX_PROTOCOL_SAVE             1055
+#define IDC_LITEX_PROTOCOL_DELETE           1056
 
 #define IDD_SETUP_LITEX                     1001
+#define IDD_LITEX_BOARD_NAME                1016
+#define IDD_LITEX_PROGRESS                  1033
 
 #ifdef APSTUDIO_INVOKED
     #ifndef APSTUDIO_READONLY_SYMBOLS
         #define _APS_NO_MFC                 1
         #define _APS_NEXT_RESOURCE_VALUE    100
         #define _APS_NEXT_COMMAND_VALUE     100
-        #define _APS_NEXT_CONTROL_VALUE     1006
+        #define _APS_NEXT_CONTROL_VALUE     1057
         #define _APS_NEXT_SYMED_VALUE       300
     #endif
 #endif
@@ -0,0 +1,222 @@
+/*! The LiteX BIOS's boot banner.
+
+Before it asks for an SFL upload, the LiteX BIOS describes the SoC and tests
+its RAM:
+
+```text
+ LiteX git sha1: 2a4b1a3d
+
+--=============== SoC ==================--
+CPU:            VexRiscv @ 100MHz
+...
+MAIN-RAM:       256.0MiB
+...
+Memtest at 0x40000000 (2.0MiB)...
+```
+
+The Memtest line gives the start of main RAM, which is where firmware
+usually goes. [`BannerScanner`] picks these lines out of the received bytes,
+so the plugin can offer (or use) that address instead of the user having to
+read it off the screen. */
+
+use std::fmt;
+
+use parse_int::parse;
+use pretty_bytes_typed::pretty_bytes_binary;
+
+/// Longest line worth keeping. Anything longer isn't part of the banner.
+const MAX_LINE: usize = 256;
+
+/// What the banner said about the SoC.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiosInfo {
+    /// CPU type and clock, e.g. `VexRiscv @ 100MHz`.
+    pub cpu: Option<String>,
+    /// LiteX commit the SoC was built from.
+    pub litex_sha1: Option<String>,
+    /// Size of main RAM in bytes.
+    pub main_ram: Option<u64>,
+    /// Where the BIOS tested memory; the start of main RAM.
+    pub memtest: Option<u32>,
+}
+
+impl fmt::Display for BiosInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(cpu) = &self.cpu {
+            parts.push(cpu.clone());
+        }
+        if let Some(size) = self.main_ram {
+            parts.push(format!("{} main RAM", pretty_bytes_binary(size, Some(1))));
+        }
+        if let Some(sha1) = &self.litex_sha1 {
+            parts.push(format!("LiteX {}", sha1));
+        }
+
+        if parts.is_empty() {
+            write!(f, "LiteX BIOS")
+        } else {
+            write!(f, "LiteX BIOS ({})", parts.join(", "))
+        }
+    }
+}
+
+/// Splits received bytes into lines and looks for the banner in them.
+#[derive(Debug, Default)]
+pub struct BannerScanner {
+    line: Vec<u8>,
+    info: BiosInfo,
+}
+
+impl BannerScanner {
+    /// Look at received bytes. Returns what the banner said once its Memtest
+    /// line goes by.
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<BiosInfo> {
+        let mut found = None;
+
+        for b in chunk {
+            match b {
+                b'\n' | b'\r' => {
+                    let line = strip_escapes(&String::from_utf8_lossy(&self.line));
+                    self.line.clear();
+                    if self.parse_line(line.trim()) {
+                        found = Some(self.info.clone());
+                    }
+                }
+                _ if self.line.len() < MAX_LINE => self.line.push(*b),
+                _ => {}
+            }
+        }
+
+        found
+    }
+
+    /// Note anything `line` says. Returns true for the Memtest line.
+    fn parse_line(&mut self, line: &str) -> bool {
+        if line.starts_with("BIOS built on") {
+            // A new boot; forget the last one.
+            self.info = BiosInfo::default();
+        } else if let Some(sha1) = line.strip_prefix("LiteX git sha1:") {
+            self.info.litex_sha1 = Some(sha1.trim().to_string());
+        } else if let Some(cpu) = line.strip_prefix("CPU:") {
+            self.info.cpu = Some(cpu.trim().to_string());
+        } else if let Some(size) = line.strip_prefix("MAIN-RAM:") {
+            self.info.main_ram = size.split_whitespace().next().and_then(parse_size);
+        } else if let Some(rest) = line.strip_prefix("Memtest at ") {
+            let addr = rest
+                .split_whitespace()
+                .next()
+                .and_then(|a| parse::<u32>(a).ok());
+            if addr.is_some() {
+                self.info.memtest = addr;
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Remove ANSI escape sequences, which some BIOS builds use for bold text.
+fn strip_escapes(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1B' {
+            // CSI: ESC [ parameters final-byte
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Sizes as the BIOS prints them, e.g. `256.0MiB` or `8.0KiB`.
+fn parse_size(size: &str) -> Option<u64> {
+    let split = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(split);
+    let scale: u64 = match unit {
+        "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return None,
+    };
+
+    let number: f64 = number.parse().ok()?;
+    Some((number * scale as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BANNER: &str = " BIOS built on Jan  1 2024 12:00:00\r\n \
+        BIOS CRC passed (1e5fd8a1)\r\n\r\n \
+        LiteX git sha1: 2a4b1a3d\r\n\r\n\
+        --=============== \x1B[1mSoC\x1B[0m ==================--\r\n\
+        \x1B[1mCPU\x1B[0m:\t\tVexRiscv @ 100MHz\r\n\
+        \x1B[1mMAIN-RAM\x1B[0m:\t256.0MiB\r\n\r\n\
+        Memtest at 0x40000000 (2.0MiB)...\r\n";
+
+    #[test]
+    fn whole_banner() {
+        let mut scanner = BannerScanner::default();
+        let info = scanner.feed(BANNER.as_bytes()).unwrap();
+
+        assert_eq!(info.memtest, Some(0x4000_0000));
+        assert_eq!(info.cpu.as_deref(), Some("VexRiscv @ 100MHz"));
+        assert_eq!(info.main_ram, Some(256 << 20));
+        assert_eq!(info.litex_sha1.as_deref(), Some("2a4b1a3d"));
+        assert_eq!(
+            info.to_string(),
+            "LiteX BIOS (VexRiscv @ 100MHz, 256 MiB main RAM, LiteX 2a4b1a3d)"
+        );
+    }
+
+    #[test]
+    fn split_across_reads() {
+        let mut scanner = BannerScanner::default();
+        let mut found = None;
+        for chunk in BANNER.as_bytes().chunks(7) {
+            found = found.or(scanner.feed(chunk));
+        }
+
+        assert_eq!(found.unwrap().memtest, Some(0x4000_0000));
+    }
+
+    #[test]
+    fn new_boot_forgets_old_one() {
+        let mut scanner = BannerScanner::default();
+        scanner.feed(BANNER.as_bytes());
+
+        let info = scanner
+            .feed(b"BIOS built on Feb  2 2024\r\nMemtest at 0x21010000 (64.0KiB)...\r\n")
+            .unwrap();
+        assert_eq!(info.memtest, Some(0x2101_0000));
+        assert_eq!(info.cpu, None);
+    }
+
+    #[test]
+    fn ignores_other_output() {
+        let mut scanner = BannerScanner::default();
+        assert_eq!(scanner.feed(b"litex> mem_test 0x40000000\r\n"), None);
+        assert_eq!(scanner.feed(b"Memtest at nowhere\r\n"), None);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("8.0KiB"), Some(8192));
+        assert_eq!(parse_size("1.5GiB"), Some(3 << 29));
+        assert_eq!(parse_size("4GB"), None);
+    }
+}
@@ -0,0 +1,88 @@
+/*! Named board profiles.
+
+A board profile is a saved set of upload settings (file, addresses and
+protocol), so that switching between boards with different RAM bases or
+firmware doesn't mean retyping everything in the dialog. */
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::sfl::{MagicMatcher, Profile};
+use super::state::{State, DEFAULT_ADDR};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Board {
+    pub name: String,
+    pub filename: Option<PathBuf>,
+    pub addr: u32,
+    pub jump_addr: Option<u32>,
+    pub protocol: Profile,
+    /// LiteX `csr.json` describing the board's memory regions.
+    pub csr_json: Option<PathBuf>,
+    /// Memory region in `csr_json` the file is loaded into, or `None` for
+    /// whichever contains the boot address.
+    pub region: Option<String>,
+    /// Extra symbols for address expressions, on top of those from
+    /// `csr_json`.
+    pub symbols: BTreeMap<String, u32>,
+}
+
+impl Board {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            filename: None,
+            addr: DEFAULT_ADDR,
+            jump_addr: None,
+            protocol: Profile::litex(),
+            csr_json: None,
+            region: None,
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    /// Snapshot the current upload settings under `name`.
+    pub fn from_state(name: &str, s: &State) -> Self {
+        Self {
+            name: name.to_string(),
+            filename: s.filename.clone(),
+            addr: s.addr,
+            jump_addr: s.jump_addr,
+            protocol: s.protocol.clone(),
+            csr_json: s.csr_json.clone(),
+            region: s.region.clone(),
+            symbols: s.symbols.clone(),
+        }
+    }
+
+    /// Make this board's settings the current upload settings. Does not
+    /// change whether the plugin is armed.
+    pub fn apply(&self, s: &mut State) {
+        s.filename = self.filename.clone();
+        s.addr = self.addr;
+        s.jump_addr = self.jump_addr;
+        s.matcher = MagicMatcher::new(&self.protocol.magic);
+        s.protocol = self.protocol.clone();
+        s.csr_json = self.csr_json.clone();
+        s.region = self.region.clone();
+        s.symbols = self.symbols.clone();
+    }
+}
+
+/// Board last used on the given serial port, if any.
+pub fn last_used(s: &State, com_port: u16) -> Option<&Board> {
+    s.port_boards
+        .get(&com_port)
+        .and_then(|name| s.boards.iter().find(|b| &b.name == name))
+}
+
+/// Current serial port, or `None` if Tera Term is connected to something else.
+pub fn com_port(s: &State) -> Option<u16> {
+    if s.cv.is_null() {
+        return None;
+    }
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let (port_type, com_port) = unsafe { ((*s.cv).PortType, (*s.cv).ComPort) };
+    (port_type as u32 == super::tt::IdSerial).then_some(com_port)
+}
@@ -0,0 +1,275 @@
+/*! Memory regions from a LiteX `csr.json`.
+
+LiteX writes a `csr.json` next to the gateware it builds. Its `memories`
+object lists the SoC's memory regions, e.g.:
+
+```json
+"memories": {
+    "rom": {"base": 0, "size": 131072, "type": "cached"},
+    "sram": {"base": 268435456, "size": 8192, "type": "cached"},
+    "main_ram": {"base": 1073741824, "size": 268435456, "type": "cached"}
+}
+```
+
+With one of these, the plugin can offer the regions as load targets instead
+of relying on the user to read the boot address off the BIOS's Memtest line,
+and can refuse an image that won't fit before sending any of it. */
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pretty_bytes_typed::pretty_bytes;
+use serde::Deserialize;
+
+use super::expr::Symbols;
+use super::Error;
+
+/// Region the BIOS keeps its stack and data in.
+pub const STACK_REGION: &str = "sram";
+/// Regions that can't be written with `Load` frames.
+const NOT_LOADABLE: &[&str] = &["csr", "rom"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub base: u32,
+    pub size: u64,
+}
+
+impl Region {
+    /// One past the last address in the region.
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.base as u64..self.end()).contains(&(addr as u64))
+    }
+
+    /// Whether any of the `len` bytes from `base` are in the region.
+    pub fn overlaps(&self, base: u32, len: u64) -> bool {
+        (base as u64) < self.end() && base as u64 + len > self.base as u64
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:#010x}, {})",
+            self.name,
+            self.base,
+            pretty_bytes(self.size, Some(1))
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrJson {
+    #[serde(default)]
+    memories: BTreeMap<String, Memory>,
+}
+
+#[derive(Deserialize)]
+struct Memory {
+    base: u64,
+    size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CsrMap {
+    pub path: PathBuf,
+    /// Sorted by base address.
+    pub regions: Vec<Region>,
+}
+
+impl CsrMap {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bad_config = |msg: String| Error::BadConfig {
+            path: path.to_path_buf(),
+            msg,
+        };
+
+        let text = fs::read_to_string(path).map_err(|e| bad_config(e.to_string()))?;
+        let mut map = Self::parse(&text).map_err(bad_config)?;
+        map.path = path.to_path_buf();
+
+        Ok(map)
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let json: CsrJson = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+        let mut regions = json
+            .memories
+            .into_iter()
+            .map(|(name, mem)| {
+                let base = u32::try_from(mem.base)
+                    .map_err(|_| format!("{} is above the 32-bit address space", name))?;
+                Ok(Region {
+                    name,
+                    base,
+                    size: mem.size,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if regions.is_empty() {
+            return Err("no memory regions listed".to_string());
+        }
+        regions.sort_by_key(|r| r.base);
+
+        Ok(Self {
+            path: PathBuf::new(),
+            regions,
+        })
+    }
+
+    pub fn region(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    /// Symbols for address expressions: `<region>` and `<region>_end` for
+    /// the start and one past the end of each region, and `<region>_size`.
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::default();
+        for r in &self.regions {
+            symbols.insert(&r.name, r.base as u64);
+            symbols.insert(&format!("{}_end", r.name), r.end());
+            symbols.insert(&format!("{}_size", r.name), r.size);
+        }
+
+        symbols
+    }
+
+    /// Regions an image can be uploaded to.
+    pub fn load_targets(&self) -> impl Iterator<Item = &Region> {
+        self.regions
+            .iter()
+            .filter(|r| !NOT_LOADABLE.contains(&r.name.as_str()))
+    }
+
+    /// Check that an image of `len` bytes at `base` fits in `region`, or in
+    /// whichever load target contains `base` if `region` is `None`.
+    ///
+    /// On success, returns a warning if the image would overwrite the
+    /// BIOS's stack.
+    pub fn check(
+        &self,
+        region: Option<&str>,
+        base: u32,
+        len: u64,
+    ) -> Result<Option<String>, String> {
+        let target = match region {
+            Some(name) => self
+                .region(name)
+                .ok_or_else(|| format!("{} has no {} region", self.path.display(), name))?,
+            None => self
+                .load_targets()
+                .find(|r| r.contains(base))
+                .ok_or_else(|| format!("{:#010x} is not in any loadable memory region", base))?,
+        };
+
+        if !target.contains(base) {
+            return Err(format!("{:#010x} is outside {}", base, target));
+        }
+        let end = base as u64 + len;
+        if end > target.end() {
+            return Err(format!(
+                "image overflows {} by {} bytes",
+                target,
+                end - target.end()
+            ));
+        }
+
+        Ok(self
+            .region(STACK_REGION)
+            .filter(|stack| stack.overlaps(base, len))
+            .map(|stack| format!("image overlaps {}, where the BIOS keeps its stack", stack)))
+    }
+
+    /// [`check`](Self::check) each image, given as base and length in
+    /// upload order. Only the first goes to `region`; the rest go wherever
+    /// their own address says.
+    pub fn check_images<I>(&self, region: Option<&str>, images: I) -> Result<Vec<String>, String>
+    where
+        I: IntoIterator<Item = (u32, u64)>,
+    {
+        let mut warnings = Vec::new();
+        for (i, (base, len)) in images.into_iter().enumerate() {
+            let region = if i == 0 { region } else { None };
+            warnings.extend(self.check(region, base, len)?);
+        }
+
+        Ok(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSR_JSON: &str = r#"{
+        "csr_bases": {"ctrl": 4026531840},
+        "memories": {
+            "rom": {"base": 0, "size": 131072, "type": "cached"},
+            "sram": {"base": 268435456, "size": 8192, "type": "cached"},
+            "main_ram": {"base": 1073741824, "size": 268435456, "type": "cached"},
+            "csr": {"base": 4026531840, "size": 65536, "type": "io"}
+        }
+    }"#;
+
+    #[test]
+    fn regions_sorted_by_base() {
+        let map = CsrMap::parse(CSR_JSON).unwrap();
+        let names: Vec<_> = map.regions.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["rom", "sram", "main_ram", "csr"]);
+
+        let targets: Vec<_> = map.load_targets().map(|r| r.name.as_str()).collect();
+        assert_eq!(targets, ["sram", "main_ram"]);
+    }
+
+    #[test]
+    fn image_fits() {
+        let map = CsrMap::parse(CSR_JSON).unwrap();
+        assert_eq!(
+            map.check(Some("main_ram"), 0x4000_0000, 0x1000_0000),
+            Ok(None)
+        );
+        assert_eq!(map.check(None, 0x4100_0000, 1024), Ok(None));
+    }
+
+    #[test]
+    fn image_overflows() {
+        let map = CsrMap::parse(CSR_JSON).unwrap();
+        let err = map.check(Some("main_ram"), 0x4fff_ff00, 0x200).unwrap_err();
+        assert!(err.contains("overflows main_ram"), "{}", err);
+        assert!(err.ends_with("by 256 bytes"), "{}", err);
+
+        assert!(map.check(Some("main_ram"), 0x1000_0000, 16).is_err());
+        assert!(map.check(None, 0x2000_0000, 16).is_err());
+        assert!(map.check(Some("spiflash"), 0x4000_0000, 16).is_err());
+    }
+
+    #[test]
+    fn warns_about_stack() {
+        let map = CsrMap::parse(CSR_JSON).unwrap();
+        let warning = map.check(None, 0x1000_0000, 16).unwrap();
+        assert!(warning.unwrap().contains("sram"));
+    }
+
+    #[test]
+    fn region_symbols() {
+        let symbols = CsrMap::parse(CSR_JSON).unwrap().symbols();
+        assert_eq!(symbols.get("main_ram"), Some(0x4000_0000));
+        assert_eq!(symbols.get("sram_end"), Some(0x1000_2000));
+        assert_eq!(symbols.get("rom_size"), Some(0x2_0000));
+    }
+
+    #[test]
+    fn rejects_bad_json() {
+        assert!(CsrMap::parse("{").is_err());
+        assert!(CsrMap::parse(r#"{"memories": {}}"#).is_err());
+    }
+}
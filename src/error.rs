@@ -1,5 +1,6 @@
 /*! Plugin Error type. */
 
+use std::path::PathBuf;
 use std::{fmt, io};
 
 #[derive(Debug)]
@@ -8,8 +9,10 @@ pub enum Error {
     OutBuffOutOfBounds(u32),
     OutBuffFull { need: u32, actual: u32 },
     FileIoError(io::Error),
-    BadAddressError(String),
+    BadAddressError { addr: String, why: String },
     UnexpectedResponse(u8),
+    BadOption { name: String, value: String },
+    BadConfig { path: PathBuf, msg: String },
 }
 
 impl fmt::Display for Error {
@@ -35,12 +38,8 @@ impl fmt::Display for Error {
             Error::FileIoError(e) => {
                 write!(f, "Could not open or read kernel file: {}", e)
             }
-            Error::BadAddressError(a) => {
-                write!(
-                    f,
-                    "Could not intepret address as a decimal or hex integer: {}",
-                    a
-                )
+            Error::BadAddressError { addr, why } => {
+                write!(f, "Could not interpret address {}: {}", addr, why)
             }
             Error::UnexpectedResponse(r) => {
                 write!(
@@ -49,6 +48,12 @@ impl fmt::Display for Error {
                     r, *r as char
                 )
             }
+            Error::BadOption { name, value } => {
+                write!(f, "Invalid value for {}: {}", name, value)
+            }
+            Error::BadConfig { path, msg } => {
+                write!(f, "Could not use {}: {}", path.display(), msg)
+            }
         }
     }
 }
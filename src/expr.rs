@@ -0,0 +1,232 @@
+/*! Address expressions.
+
+Anywhere the dialog takes an address, it also takes a sum of numbers and
+symbols, such as `main_ram + 0x10000`, `sram_end - 4K` or
+`0x4000_0000 + 1M`. Numbers are anything [`parse_int`] understands, and may
+end in `K`, `M` or `G` for KiB, MiB or GiB. Parentheses group terms. Symbols
+come from the board's `csr.json` (see [`CsrMap::symbols`]) and its symbol
+table, and are case-insensitive.
+
+[`CsrMap::symbols`]: super::csr::CsrMap::symbols */
+
+use std::collections::BTreeMap;
+
+use parse_int::parse;
+
+use super::Error;
+
+/// Values that symbols in an expression stand for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols(BTreeMap<String, u64>);
+
+impl Symbols {
+    pub fn insert(&mut self, name: &str, value: u64) {
+        self.0.insert(name.to_ascii_lowercase(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.0.get(&name.to_ascii_lowercase()).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(u64),
+    Sym(String),
+    Plus,
+    Minus,
+    Open,
+    Close,
+}
+
+fn number(word: &str) -> Result<u64, String> {
+    if let Ok(n) = parse::<u64>(word) {
+        return Ok(n);
+    }
+
+    let (digits, suffix) = word.split_at(word.len() - 1);
+    let scale: u64 = match suffix {
+        "k" | "K" => 1 << 10,
+        "m" | "M" => 1 << 20,
+        "g" | "G" => 1 << 30,
+        _ => return Err(format!("{} is not a number", word)),
+    };
+    parse::<u64>(digits)
+        .map_err(|_| format!("{} is not a number", word))?
+        .checked_mul(scale)
+        .ok_or_else(|| format!("{} is too large", word))
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = i + c.len_utf8();
+                }
+
+                let word = &expr[start..end];
+                if c.is_ascii_digit() {
+                    Token::Num(number(word)?)
+                } else {
+                    Token::Sym(word.to_string())
+                }
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    /// `term (('+' | '-') term)*`
+    fn sum(&mut self) -> Result<i128, String> {
+        let mut value = self.term()?;
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::Plus) => {
+                    self.tokens.next();
+                    value += self.term()?;
+                }
+                Some(Token::Minus) => {
+                    self.tokens.next();
+                    value -= self.term()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// A number, a symbol, or a parenthesized sum.
+    fn term(&mut self) -> Result<i128, String> {
+        match self.tokens.next() {
+            Some(Token::Num(n)) => Ok(n.into()),
+            Some(Token::Sym(name)) => self
+                .symbols
+                .get(&name)
+                .map(i128::from)
+                .ok_or_else(|| format!("unknown symbol {}", name)),
+            Some(Token::Open) => {
+                let value = self.sum()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(Token::Close) => Err("unexpected ')'".to_string()),
+            Some(_) => Err("expected a number or symbol before '+' or '-'".to_string()),
+            None => Err("expected a number or symbol at the end".to_string()),
+        }
+    }
+}
+
+/// Work out the address `expr` stands for. Errors say what's wrong, but not
+/// with which expression.
+pub fn eval(expr: &str, symbols: &Symbols) -> Result<u32, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?.into_iter().peekable(),
+        symbols,
+    };
+    if parser.tokens.peek().is_none() {
+        return Err("no address given".to_string());
+    }
+
+    let value = parser.sum()?;
+    if let Some(token) = parser.tokens.next() {
+        return Err(match token {
+            Token::Close => "unexpected ')'".to_string(),
+            _ => "expected '+' or '-' between terms".to_string(),
+        });
+    }
+
+    if value < 0 {
+        return Err(format!("result is negative ({})", value));
+    }
+    u32::try_from(value).map_err(|_| format!("result overflows 32 bits ({:#x})", value))
+}
+
+/// [`eval`], with errors that say which address was wrong.
+pub fn parse_address(expr: &str, symbols: &Symbols) -> Result<u32, Error> {
+    eval(expr, symbols).map_err(|why| Error::BadAddressError {
+        addr: expr.to_string(),
+        why,
+    })
+}
+
+/// Whether `expr` is just a number, so there's no point showing what it
+/// resolves to.
+pub fn is_literal(expr: &str) -> bool {
+    parse::<u32>(expr.trim()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> Symbols {
+        let mut symbols = Symbols::default();
+        symbols.insert("main_ram", 0x4000_0000);
+        symbols.insert("sram_end", 0x1000_2000);
+        symbols
+    }
+
+    #[test]
+    fn literals() {
+        let none = Symbols::default();
+        assert_eq!(eval("0x40000000", &none), Ok(0x4000_0000));
+        assert_eq!(eval("0x4000_0000", &none), Ok(0x4000_0000));
+        assert_eq!(eval("1024", &none), Ok(1024));
+        assert_eq!(eval("4K", &none), Ok(4096));
+        assert_eq!(eval("1m", &none), Ok(0x10_0000));
+        assert_eq!(eval("2G", &none), Ok(0x8000_0000));
+    }
+
+    #[test]
+    fn sums() {
+        let s = symbols();
+        assert_eq!(eval("main_ram + 0x10000", &s), Ok(0x4001_0000));
+        assert_eq!(eval("sram_end - 4K", &s), Ok(0x1000_1000));
+        assert_eq!(eval("0x4000_0000 + 1M", &s), Ok(0x4010_0000));
+        assert_eq!(eval("MAIN_RAM+1M-(4K+4K)", &s), Ok(0x400f_e000));
+    }
+
+    #[test]
+    fn errors() {
+        let s = symbols();
+        assert_eq!(eval("rom + 4", &s), Err("unknown symbol rom".to_string()));
+        assert!(eval("main_ram + 3G", &s).unwrap_err().contains("overflows"));
+        assert!(eval("4K - 8K", &s).unwrap_err().contains("negative"));
+        assert!(eval("", &s).is_err());
+        assert!(eval("main_ram +", &s).is_err());
+        assert!(eval("(main_ram", &s).is_err());
+        assert!(eval("main_ram 4", &s).is_err());
+        assert!(eval("12Q", &s).is_err());
+        assert!(eval("main_ram * 2", &s).is_err());
+    }
+
+    #[test]
+    fn literal_check() {
+        assert!(is_literal(" 0x4000_0000 "));
+        assert!(!is_literal("main_ram"));
+        assert!(!is_literal("4K"));
+    }
+}
@@ -0,0 +1,80 @@
+/*! Recently used files and addresses.
+
+Offered in the setup dialog's drop-downs, most recent first. Unlike the rest
+of the settings, history is written to `TERATERM.INI` as soon as the dialog
+is closed with OK, the same way Tera Term remembers recent hosts. */
+
+use std::path::{Path, PathBuf};
+
+use super::setup::Ini;
+use super::Error;
+
+/// Entries kept in each list.
+pub const MAX_ENTRIES: usize = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct History {
+    pub files: Vec<PathBuf>,
+    pub addrs: Vec<u32>,
+    /// Directory the file picker was last used in.
+    pub last_dir: Option<PathBuf>,
+}
+
+/// Move `item` to the front of `list`, dropping the oldest entry if full.
+fn push_front<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    list.retain(|i| *i != item);
+    list.insert(0, item);
+    list.truncate(MAX_ENTRIES);
+}
+
+impl History {
+    pub fn push_file(&mut self, file: &Path) {
+        push_front(&mut self.files, file.to_path_buf());
+    }
+
+    pub fn push_addr(&mut self, addr: u32) {
+        push_front(&mut self.addrs, addr);
+    }
+
+    /// Where the file picker should start: the last directory it was used
+    /// in, or failing that, the directory of `current`.
+    pub fn picker_dir(&self, current: Option<&Path>) -> Option<PathBuf> {
+        self.last_dir
+            .clone()
+            .or_else(|| current.and_then(Path::parent).map(Path::to_path_buf))
+            .filter(|d| d.is_dir())
+    }
+
+    pub fn load(ini: &Ini) -> Self {
+        let files = (1..=MAX_ENTRIES)
+            .filter_map(|i| ini.get(&format!("File{}", i)))
+            .map(PathBuf::from)
+            .collect();
+        let addrs = (1..=MAX_ENTRIES)
+            .filter_map(|i| ini.get_u32(&format!("Address{}", i)))
+            .collect();
+
+        Self {
+            files,
+            addrs,
+            last_dir: ini.get("LastDirectory").map(PathBuf::from),
+        }
+    }
+
+    pub fn save(&self, ini: &Ini) -> Result<(), Error> {
+        // Clear out old entries, in case the lists got shorter.
+        ini.delete()?;
+
+        for (i, file) in self.files.iter().enumerate() {
+            ini.set(&format!("File{}", i + 1), &file.to_string_lossy())?;
+        }
+        for (i, addr) in self.addrs.iter().enumerate() {
+            ini.set(&format!("Address{}", i + 1), &format!("{:#010x}", addr))?;
+        }
+        if let Some(dir) = &self.last_dir {
+            ini.set("LastDirectory", &dir.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+}
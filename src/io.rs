@@ -2,26 +2,45 @@
 
 use core::slice;
 use std::ffi::c_void;
-use std::fmt::Write;
+use std::fmt;
 use std::fs::File;
-use std::time::Instant;
-use std::{io, ptr};
+use std::ptr;
+use std::time::{Duration, Instant};
 
-use super::sfl::{Resp, SflLoader, MAGIC_RESPONSE};
-use super::state::{Activity, State, TTX_LITEX_STATE};
+use super::csr::CsrMap;
+use super::options::MessageLevel;
+use super::plan::Plan;
+use super::progress::{status_line, Progress};
+use super::progress_window;
+use super::sfl::{
+    profile, AfterJump, Cmd, JumpWatch, MagicMatcher, Received, Resp, ResponseFilter, SflLoader,
+    MIN_CHUNK_SIZE,
+};
+use super::state::{Activity, InFlight, ResumePoint, State, TTX_LITEX_STATE};
+use super::stats::TransferStats;
 use super::tt;
+use super::ui;
+use super::watch::{self, Stamp};
 use super::Error;
 
 use log::*;
 use pretty_bytes_typed::pretty_bytes;
+use windows::Win32::Foundation::HWND;
 use windows::Win32::System::IO::OVERLAPPED;
+use windows::Win32::UI::WindowsAndMessaging::{KillTimer, SetTimer};
 
 enum ReadAction {
     PassThru,
     Swallow,
-    Replace(String),
+    /// Bytes for the terminal, as they're to be shown: our messages as
+    /// UTF-8, and anything else the receiver sent as it came.
+    Replace(Vec<u8>),
     Append(String),
-    Prepend(String),
+    /// Replace the first `consumed` bytes with `text`, keeping the rest.
+    Splice {
+        consumed: usize,
+        text: Vec<u8>,
+    },
 }
 
 ttx_export! {
@@ -44,7 +63,13 @@ ttx_export! {
                 trace!(target: "our_p_read_file", "Running original PReadFile at {:?}.", read_file);
                 rf_ret = read_file(fh, buff, len, read_bytes, wol);
 
+                // Overlapped read still pending; buff isn't ours to touch yet.
+                if rf_ret == 0 {
+                    return Ok(rf_ret);
+                }
+
                 if *read_bytes == 0 {
+                    flush_notices(s, buff as *mut u8, len, read_bytes);
                     return Ok(rf_ret);
                 }
 
@@ -56,14 +81,17 @@ ttx_export! {
                 // terminal output, so we inject anything we want to write to the
                 // screen as the return value of our hook.
                 let chunk = slice::from_raw_parts(buff as *const u8, *read_bytes as usize);
+                // Even mid-upload, as the board may have been reset.
+                watch_banner(s, chunk);
                 match drive_sfl(&mut s, chunk)? {
                     ReadAction::PassThru => {}
                     ReadAction::Swallow => {
                         *read_bytes = 0;
                     }
-                    ReadAction::Replace(s) => {
-                        ptr::copy_nonoverlapping(s.as_ptr(), buff as *mut u8, s.len());
-                        *read_bytes = s.len() as u32;
+                    ReadAction::Replace(text) => {
+                        let shown = defer_overflow(s, text, len as usize, len as usize);
+                        ptr::copy_nonoverlapping(shown.as_ptr(), buff as *mut u8, shown.len());
+                        *read_bytes = shown.len() as u32;
                     }
                     ReadAction::Append(s) => {
                         if (len - *read_bytes) >= (s.len() as u32) {
@@ -75,19 +103,16 @@ ttx_export! {
                             *read_bytes += s.len() as u32;
                         }
                     }
-                    ReadAction::Prepend(s) => {
-                        if (len - *read_bytes) >= (s.len() as u32) {
-                            // XXX: Remove the last acknowledgment 'K' in the string. :)
-                            // Maybe I should make an "ReplaceFirstAndPrepend" action.
-                            *(buff as *mut u8) = b'\r';
-
-                            ptr::copy(buff, buff.offset(s.len() as isize), *read_bytes as usize);
-                            ptr::copy_nonoverlapping(s.as_ptr(), buff as *mut u8, s.len());
-                            *read_bytes += s.len() as u32;
-                        }
+                    ReadAction::Splice { consumed, text } => {
+                        let rest = *read_bytes as usize - consumed;
+                        let shown = defer_overflow(s, text, len as usize - rest, len as usize);
+                        ptr::copy(buff.add(consumed), buff.add(shown.len()), rest);
+                        ptr::copy_nonoverlapping(shown.as_ptr(), buff as *mut u8, shown.len());
+                        *read_bytes = (rest + shown.len()) as u32;
                     }
                 }
 
+                flush_notices(s, buff as *mut u8, len, read_bytes);
                 Ok::<_, Error>(rf_ret)
             })
             .inspect_err(|e| error!(target: "our_p_read_file", "Failed to drive SFL FSM: {}", e))
@@ -95,226 +120,1076 @@ ttx_export! {
     }
 }
 
-fn drive_sfl(s: &mut State, chunk: &[u8]) -> Result<ReadAction, Error> {
-    fn redo_last_frame(s: &mut State, err: Resp) -> Result<(), Error> {
-        info!(target: "drive_sfl", "SFL Error: {}, resending current", err);
-        let frame = s
-            .curr_frame
-            .take()
-            .expect("a previous frame should've been saved before asking to redo a frame");
-        trace!("resend: {:X?}", frame);
-        inject_output(s, frame.as_bytes())?;
-        s.curr_frame = Some(frame);
+/// Format a line for the terminal, or return an empty string if messages at
+/// `level` are turned off. Everything the plugin writes into the terminal
+/// goes through here, so that the message level and color apply to all of
+/// it.
+fn message<T>(s: &State, level: MessageLevel, msg: T) -> String
+where
+    T: fmt::Display,
+{
+    if s.options.message_level < level {
+        return String::new();
+    }
 
-        Ok(())
+    // Clear to the end of the line, in case this overwrites a longer one.
+    match s.options.message_color {
+        Some(color) => format!("\x1B[0;{}m[TTXLiteX] {}\x1B[0m\x1B[K", color.sgr(), msg),
+        None => format!("[TTXLiteX] {}\x1B[K", msg),
     }
+}
 
-    fn status_bar(s: &State) -> String {
-        let chunk_size = s
-            .sfl_loader
-            .as_ref()
-            .expect("s.sfl_loader should have been initialized by Activity::LookForMagic")
-            .chunk_size;
-        let file_size = s
-            .file_size
-            .expect("s.file_size should have been initialized by Activity::LookForMagic");
-        let mut total_chunks = file_size / (chunk_size as u64);
-        let rem = file_size % (chunk_size as u64);
-        if rem != 0 {
-            total_chunks += 1;
-        }
+/// [`message`], as a line of its own.
+fn message_line<T>(s: &State, level: MessageLevel, msg: T) -> String
+where
+    T: fmt::Display,
+{
+    let mut line = message(s, level, msg);
+    if !line.is_empty() {
+        line.push_str("\r\n");
+    }
+
+    line
+}
 
-        const BAR_LENGTH: u64 = 40;
-        let chunk_no = (s.last_frame_acked.unwrap() + 1) as u64;
-        let used_part = (BAR_LENGTH * chunk_no) / total_chunks;
+/// Queue a message for the terminal. Messages are shown the next time
+/// Tera Term reads from the port, ahead of whatever was received.
+pub fn notice<T>(s: &mut State, level: MessageLevel, msg: T)
+where
+    T: fmt::Display,
+{
+    let line = message_line(s, level, msg);
+    if !line.is_empty() {
+        s.notices.push_back(format!("\r\n{}", line).into());
+    }
+}
 
-        let mut bar = String::with_capacity(BAR_LENGTH as usize);
+/// Cut `text` down to the `room` left in InBuff, queueing the rest to be
+/// shown on the following reads, `len` bytes at a time, ahead of any other
+/// notices. Tera Term decodes what it reads as one stream, so a character
+/// split across reads still comes out whole.
+fn defer_overflow(s: &mut State, mut text: Vec<u8>, room: usize, len: usize) -> Vec<u8> {
+    let rest = text.split_off(text.len().min(room));
+    for piece in rest.chunks(len.max(1)).rev() {
+        s.notices.push_front(piece.to_vec());
+    }
 
-        for _ in 0..used_part {
-            bar.push('=');
-        }
+    text
+}
 
-        // Arrow goes away once 100% loaded!
-        if used_part != BAR_LENGTH {
-            bar.push('>');
+unsafe fn flush_notices(s: &mut State, buff: *mut u8, len: u32, read_bytes: *mut u32) {
+    while let Some(n) = s.notices.front() {
+        if n.len() as u32 > len {
+            warn!(
+                target: "flush_notices",
+                "Dropping notice too long for InBuff: {}",
+                String::from_utf8_lossy(n)
+            );
+            s.notices.pop_front();
+            continue;
         }
 
-        for _ in (used_part + 1)..BAR_LENGTH {
-            bar.push(' ');
+        // Try again on the next read.
+        if (len - *read_bytes) < (n.len() as u32) {
+            break;
         }
 
-        let mut resp = String::new();
-        let _ = write!(
-            &mut resp,
-            "\r\x1B[0;36m[TTXLiteX] |{}| {} / {} chunks\x1B[0m",
-            bar,
-            s.last_frame_acked.unwrap() + 1,
-            total_chunks
+        ptr::copy(buff, buff.add(n.len()), *read_bytes as usize);
+        ptr::copy_nonoverlapping(n.as_ptr(), buff, n.len());
+        *read_bytes += n.len() as u32;
+        s.notices.pop_front();
+    }
+}
+
+fn status_bar(s: &mut State) -> String {
+    let Some(progress) = Progress::current(s) else {
+        return String::new();
+    };
+
+    let bar = message(
+        s,
+        MessageLevel::Normal,
+        status_line(&progress, s.options.status_style),
+    );
+    if bar.is_empty() {
+        return bar;
+    }
+
+    format!("\r{}", bar)
+}
+
+/// Note how much has been acknowledged, for the recent transfer rate.
+fn record_rate(s: &mut State) {
+    if let Some(progress) = Progress::current(s) {
+        s.rate.record(progress.elapsed, progress.bytes_acked());
+    }
+}
+
+/// Statistics for the transfer that's ending, if the user asked for them.
+fn report(s: &mut State) -> String {
+    if !s.options.detailed_report {
+        return String::new();
+    }
+
+    if let Some(loader) = &s.sfl_loader {
+        s.stats.chunk_size = loader.chunk_size;
+    }
+    s.stats.bytes = s.file_size.unwrap_or(0);
+    s.stats.elapsed = s.start_time.map_or(Duration::ZERO, |t| Instant::now() - t);
+
+    let mut resp = String::new();
+    for line in s.stats.describe() {
+        resp.push_str(&message_line(
+            s,
+            MessageLevel::Summary,
+            format_args!("  {}", line),
+        ));
+    }
+
+    resp
+}
+
+/// How the transfer ended, followed by the report if any.
+fn outcome<T>(s: &State, msg: T, report: &str) -> String
+where
+    T: fmt::Display,
+{
+    let line = message_line(s, MessageLevel::Summary, msg);
+    if line.is_empty() {
+        return line;
+    }
+
+    format!("\r\n{}{}\r\n", line, report)
+}
+
+fn finish_transfer(s: &mut State) -> String {
+    let file_size = s
+        .file_size
+        .expect("s.file_size should have been initialized by Activity::LookForMagic")
+        as f64;
+    let mut report = report(s);
+
+    s.upload_digest = None;
+    s.resume = None;
+    s.file_size = None;
+    s.last_frame_acked = None;
+    s.last_frame_sent = None;
+    s.in_flight.clear();
+    s.forget_approval();
+    s.uploads_left = s.uploads_left.map(|n| n.saturating_sub(1));
+    if s.uploads_left == Some(0) {
+        s.disarm();
+        info!(target: "drive_sfl", "Disarmed after the last upload.");
+        report.push_str(&message_line(
+            s,
+            MessageLevel::Normal,
+            format_args!(
+                "Disarmed after {}; check \"Active\" or use Upload Now to upload again",
+                uploads(s.options.arm_mode.uploads().unwrap_or(1))
+            ),
+        ));
+    } else {
+        s.activity = Activity::LookForMagic;
+        s.restart_arm_expiry();
+    }
+    restart_timer(s);
+
+    let elapsed = (Instant::now() - s.start_time.unwrap()).as_secs_f64();
+    let rate = file_size / elapsed;
+
+    outcome(
+        s,
+        format_args!("Done! ({}/s)", pretty_bytes(rate as u64, Some(2))),
+        &report,
+    )
+}
+
+fn uploads(n: u32) -> String {
+    match n {
+        1 => "1 upload".to_string(),
+        n => format!("{} uploads", n),
+    }
+}
+
+/// Look for the LiteX BIOS's boot banner, and use or suggest the boot address
+/// it gives away.
+fn watch_banner(s: &mut State, chunk: &[u8]) {
+    let Some(info) = s.banner.feed(chunk) else {
+        return;
+    };
+    let Some(addr) = info.memtest else {
+        return;
+    };
+    info!(target: "watch_banner", "Detected {}, main RAM at {:#010x}", info, addr);
+    if let Some(point) = &mut s.resume {
+        point.rebooted = true;
+    }
+    if s.activity.in_transfer() {
+        s.rebooted_mid_upload = true;
+    }
+
+    if addr == s.addr {
+        notice(
+            s,
+            MessageLevel::Verbose,
+            format_args!("Detected {}; boot address {:#010x} matches", info, addr),
+        );
+    } else if s.options.detect_boot_addr && !s.activity.in_transfer() {
+        let old = s.addr;
+        s.addr = addr;
+        notice(
+            s,
+            MessageLevel::Normal,
+            format_args!(
+                "Detected {}; using boot address {:#010x} instead of {:#010x}",
+                info, addr, old
+            ),
         );
+    } else {
+        let current = s.addr;
+        notice(
+            s,
+            MessageLevel::Normal,
+            format_args!(
+                "Detected {}; main RAM starts at {:#010x}, but the boot address is {:#010x}",
+                info, addr, current
+            ),
+        );
+    }
+    s.bios = Some(info);
+}
+
+/// Refuse to upload images that don't fit in the memory regions listed in
+/// the board's `csr.json`, if it has one.
+fn check_regions(s: &mut State, loader: &mut SflLoader<File>) -> Result<(), Error> {
+    let Some(path) = s.csr_json.clone() else {
+        return Ok(());
+    };
+    let map = CsrMap::load(&path)?;
 
-        resp
+    let images = loader.images().map_err(Error::FileIoError)?;
+    let warnings = map
+        .check_images(s.region.as_deref(), images)
+        .map_err(|msg| Error::BadConfig { path, msg })?;
+    for warning in warnings {
+        notice(
+            s,
+            MessageLevel::Normal,
+            format_args!("Warning: {}", warning),
+        );
     }
 
-    match &mut s.activity {
+    Ok(())
+}
+
+fn drive_sfl(s: &mut State, chunk: &[u8]) -> Result<ReadAction, Error> {
+    if s.activity == Activity::WaitFinalResp {
+        match s.jump_watch.check(chunk) {
+            AfterJump::Booted => {
+                // The Jump was acknowledged, but the ack got lost. Stop
+                // resending the Jump into the running firmware, and show
+                // what it says.
+                info!(target: "drive_sfl", "Firmware output instead of the Jump ack.");
+                let text = jump_unacked(s, "but the firmware is running");
+                return Ok(ReadAction::Splice {
+                    consumed: 0,
+                    text: text.into(),
+                });
+            }
+            AfterJump::Garbled => {
+                info!(target: "drive_sfl", "Garbled reply to the Jump; the firmware may be running.");
+            }
+            AfterJump::Responses => {}
+        }
+    }
+
+    match s.activity {
         Activity::Inactive => Ok(ReadAction::PassThru),
         Activity::LookForMagic => {
+            if s.confirming {
+                return Ok(ReadAction::PassThru);
+            }
+            if s.arm_expired() {
+                s.disarm();
+                stop_trigger_timer(s);
+                info!(target: "drive_sfl", "Arming expired.");
+                let expiry = s.options.arm_expiry.unwrap_or_default();
+                notice(
+                    s,
+                    MessageLevel::Summary,
+                    format_args!(
+                        "Disarmed: no upload within {} min of arming",
+                        expiry.as_secs() / 60
+                    ),
+                );
+                return Ok(ReadAction::PassThru);
+            }
             if !s.matcher.look_for_match(chunk) {
                 return Ok(ReadAction::PassThru);
             }
 
             s.matcher.reset();
             info!(target: "drive_sfl", "Found magic string.");
-
-            let filename = s.filename.as_ref().expect(
-                "input filename should've been verified non-empty before Activity::LookForMagic",
-            );
-
-            let mut loader = File::open(filename)
-                .map_err(|e| Error::FileIoError(e))
-                .and_then(|fp| {
-                    let size = fp.metadata().map_err(|e| Error::FileIoError(e))?.len();
-
-                    if size == 0 {
-                        return Err(Error::FileIoError(io::Error::new(
-                            io::ErrorKind::UnexpectedEof,
-                            "input file was empty",
-                        )));
-                    } else {
-                        s.file_size = Some(size);
-                        Ok(fp)
-                    }
-                })
-                .map(|fp| SflLoader::new(fp, s.addr))?;
-
-            inject_output(s, &MAGIC_RESPONSE)?;
-            let frame = loader
-                .encode_data_frame(0)
-                .map_err(|e| Error::FileIoError(e))?
-                .expect("input file should've been verified to be non-empty at this point");
-            trace!("first: {:02X?}", frame);
-            inject_output(s, frame.as_bytes())?;
-            // Mutably borrowed twice???
-            // fn next_data_frame(s: &mut State) -> Result<Option<Box<Frame>>, Error> {
-            //     todo!()
-            // }
-            // inject_output(s, next_data_frame(s)?.unwrap().as_bytes())?;
-
-            s.sfl_loader = Some(loader);
-            s.activity = Activity::Calibrate;
-            s.curr_frame = Some(frame);
-            s.last_frame_sent = Some(0);
-            s.start_time = Some(Instant::now());
-
-            Ok(ReadAction::Append(
-                "\r\x1B[0;36m[TTXLiteX] Uploading File\x1B[0m\r\n".to_string(),
-            ))
+            answer_magic(s)
         }
-        Activity::Calibrate => {
-            let loader = s
-                .sfl_loader
-                .as_mut()
-                .expect("s.sfl_loader should have been initialized by Activity::LookForMagic");
-
-            let action =
-                match Resp::try_from(chunk[0]).map_err(|_| Error::UnexpectedResponse(chunk[0]))? {
-                    Resp::Success => {
-                        s.activity = Activity::WaitResp;
-                        let mut resp = String::new();
-                        let _ = write!(
-                            resp,
-                            "\x1B[0;36m[TTXLiteX] Using packet size: {} \x1B[0m\r\n",
-                            loader.chunk_size
-                        );
-                        Ok(ReadAction::Replace(resp))
+        Activity::Calibrate | Activity::WaitResp | Activity::WaitFinalResp => {
+            // Each response acknowledges the oldest frame in flight, until
+            // the transfer ends. Anything after that is the firmware talking.
+            let mut resp = Vec::new();
+            let mut progressed = false;
+            let now = Instant::now();
+
+            for (i, b) in chunk.iter().enumerate() {
+                match s.responses.classify(*b, now) {
+                    Received::Response(_) => {}
+                    Received::Output => {
+                        // Likely the boot banner of a board that reset.
+                        resp.push(*b);
+                        continue;
                     }
-                    _ => {
-                        info!(target: "drive_sfl", "Halved packet size.");
-                        loader.halve_chunk_size();
-                        Ok(ReadAction::Swallow)
+                    Received::Restarted => {
+                        resp.extend_from_slice(receiver_restarted(s).as_bytes());
+                        if s.options.restart_on_reset {
+                            if let ReadAction::Append(text) = answer_magic(s)? {
+                                resp.extend_from_slice(text.as_bytes());
+                            }
+                        }
+                        return Ok(ReadAction::Splice {
+                            consumed: i + 1,
+                            text: resp,
+                        });
                     }
-                };
+                }
 
-            // Resend frame 0 with final packet size to cleanly separate
-            // calibration and send modes.
-            let frame = loader
-                .encode_data_frame(0)
-                .map_err(|e| Error::FileIoError(e))?
-                .expect("input file should've been verified to be non-empty at this point");
+                let was = s.activity;
+                if let Some(text) = handle_resp(s, *b)? {
+                    resp.extend_from_slice(text.as_bytes());
+                }
+                progressed |= was == Activity::WaitResp && *b == Resp::Success as u8;
 
-            inject_output(s, frame.as_bytes())?;
-            action
+                if !s.activity.in_transfer() {
+                    return Ok(ReadAction::Splice {
+                        consumed: i + 1,
+                        text: resp,
+                    });
+                }
+            }
+            restart_timer(s);
+
+            if progressed {
+                record_rate(s);
+                if s.options.status_bar {
+                    resp.extend_from_slice(status_bar(s).as_bytes());
+                }
+            }
+
+            if resp.is_empty() {
+                Ok(ReadAction::Swallow)
+            } else {
+                Ok(ReadAction::Replace(resp))
+            }
         }
-        Activity::WaitResp => {
-            match Resp::try_from(chunk[0]).map_err(|_| Error::UnexpectedResponse(chunk[0]))? {
-                Resp::Success => {
-                    s.last_frame_acked = s.last_frame_sent;
-                    let next_frame = s.last_frame_sent.expect(
-                        "s.last_frame_sent should have been initialized by Activity::LookForMagic",
-                    );
+    }
+}
 
-                    let loader = s.sfl_loader.as_mut().expect(
-                        "s.sfl_loader should have been initialized by Activity::LookForMagic",
-                    );
+/// The receiver sent the magic string: answer it and start the upload,
+/// unless the user wants to be asked first.
+fn answer_magic(s: &mut State) -> Result<ReadAction, Error> {
+    stop_trigger_timer(s);
 
-                    match loader
-                        .encode_data_frame(next_frame + 1)
-                        .map_err(|e| Error::FileIoError(e))?
-                    {
-                        Some(frame) => {
-                            trace!("next: {:X?}", frame);
-                            inject_output(s, frame.as_bytes())?;
-                            s.curr_frame = Some(frame);
-                            s.last_frame_sent = Some(next_frame + 1);
-                        }
-                        None => {
-                            let frame = loader.encode_boot_frame(s.addr);
-                            trace!("final: {:X?}", frame);
-                            inject_output(s, frame.as_bytes())?;
-                            s.curr_frame = Some(frame);
-                            s.activity = Activity::WaitFinalResp;
-                        }
-                    };
+    if s.options.confirm_upload && !s.upload_approved {
+        // Don't answer; ask once this read is over.
+        ask_to_confirm(s);
+        return Ok(ReadAction::PassThru);
+    }
+    s.upload_approved = false;
+
+    // Send what the user agreed to, if they were asked. Otherwise, pick up
+    // changes to the project config since the upload was armed.
+    let plan = match s.approved_plan.take() {
+        Some(plan) => plan,
+        None => Plan::new(s).inspect_err(|e| notice(s, MessageLevel::Summary, e))?,
+    };
+    let filename = plan.images[0].0.clone();
+    let Plan {
+        settings: upload,
+        mut loader,
+        config,
+        ..
+    } = plan;
+    let chunk_size = loader.chunk_size;
+
+    if let Some(config) = &config {
+        notice(
+            s,
+            MessageLevel::Normal,
+            format_args!(
+                "Using {} ({} extra images)",
+                config.path.display(),
+                config.images.len()
+            ),
+        );
+    }
+    if s.options.watch_file {
+        match watch::describe_build(&filename) {
+            Ok(build) => notice(s, MessageLevel::Normal, format_args!("Build: {}", build)),
+            Err(e) => error!(target: "drive_sfl", "Could not describe build: {}", e),
+        }
+    }
+    s.file_size = Some(loader.total_len().map_err(Error::FileIoError)?);
+    check_regions(s, &mut loader).inspect_err(|e| notice(s, MessageLevel::Summary, e))?;
+    s.upload_digest = if s.options.resume_uploads {
+        Some(loader.digest().map_err(Error::FileIoError)?)
+    } else {
+        None
+    };
+    let resume = take_resume_point(s);
+
+    inject_output(s, &upload.protocol.response)?;
+
+    s.sfl_loader = Some(loader);
+    s.matcher = MagicMatcher::new(&upload.protocol.magic);
+    s.responses = ResponseFilter::new(&upload.protocol.magic);
+    s.upload = Some(upload);
+    s.rebooted_mid_upload = false;
+    s.in_flight.clear();
+    s.stats = TransferStats {
+        calibrated: s.options.calibrates(),
+        ..TransferStats::default()
+    };
+    s.rate.clear();
+    s.last_frame_sent = None;
+    s.last_frame_acked = None;
+    s.start_time = Some(Instant::now());
+
+    let mut resp = message_line(s, MessageLevel::Normal, "Uploading File");
+    if let Some(point) = resume {
+        let loader = s.sfl_loader.as_mut().expect("s.sfl_loader was set above");
+        loader.chunk_size = point.chunk_size;
+        s.stats.calibrated = false;
+        s.last_frame_sent = point.next_frame.checked_sub(1);
+        s.last_frame_acked = s.last_frame_sent;
+        s.activity = Activity::WaitResp;
+        resp.push_str(&message_line(
+            s,
+            MessageLevel::Normal,
+            format_args!(
+                "Resuming at frame {} (packet size {})",
+                point.next_frame, point.chunk_size
+            ),
+        ));
+        if let Some(text) = fill_window(s)? {
+            resp.push_str(&text);
+        }
+    } else if s.options.calibrates() {
+        s.activity = Activity::Calibrate;
+        send_next_frame(s)?;
+    } else {
+        s.activity = Activity::WaitResp;
+        resp.push_str(&message_line(
+            s,
+            MessageLevel::Normal,
+            format_args!("Using packet size: {}", chunk_size),
+        ));
+        fill_window(s)?;
+    }
+    if !resp.is_empty() {
+        resp.insert(0, '\r');
+    }
+    restart_timer(s);
+    if s.options.progress_window {
+        progress_window::open(s);
+    }
+
+    Ok(ReadAction::Append(resp))
+}
+
+/// Where to carry on from, if the last upload was interrupted and this one
+/// would send the same thing. Either way, there's nothing to resume after
+/// this one.
+fn take_resume_point(s: &mut State) -> Option<ResumePoint> {
+    let point = s.resume.take()?;
+    let digest = s.upload_digest?;
 
-                    let resp = status_bar(&s);
-                    Ok(ReadAction::Replace(resp))
+    let why_not = if point.digest != digest {
+        "the images or their addresses have changed"
+    } else if point.rebooted {
+        "the BIOS has rebooted and tested its RAM since"
+    } else {
+        return Some(point);
+    };
+    info!(target: "drive_sfl", "Not resuming: {}", why_not);
+    notice(
+        s,
+        MessageLevel::Normal,
+        format_args!("Starting from the beginning: {}", why_not),
+    );
+
+    None
+}
+
+/// Remember how far the upload underway got, so the next one can resume
+/// from there.
+fn save_resume_point(s: &mut State) {
+    let Some(digest) = s.upload_digest.take() else {
+        return;
+    };
+    // Frame numbers mean nothing until calibration settles on a chunk size.
+    if s.activity == Activity::Calibrate {
+        return;
+    }
+    let (Some(loader), Some(acked)) = (&s.sfl_loader, s.last_frame_acked) else {
+        return;
+    };
+
+    info!(target: "drive_sfl", "Can resume at frame {}.", acked + 1);
+    s.resume = Some(ResumePoint {
+        digest,
+        chunk_size: loader.chunk_size,
+        next_frame: acked + 1,
+        rebooted: s.rebooted_mid_upload,
+    });
+}
+
+/// End an upload whose Jump frame wasn't acknowledged, saying `why` that's
+/// still a success.
+fn jump_unacked(s: &mut State, why: &str) -> String {
+    let mut text = finish_transfer(s);
+    text.push_str(&message_line(
+        s,
+        MessageLevel::Normal,
+        format_args!("The Jump wasn't acknowledged, {}", why),
+    ));
+
+    text
+}
+
+/// Act on one response byte from the receiver. Returns text for the
+/// terminal, if any.
+fn handle_resp(s: &mut State, b: u8) -> Result<Option<String>, Error> {
+    match Resp::try_from(b).map_err(|_| Error::UnexpectedResponse(b))? {
+        Resp::Success => {
+            let acked = s
+                .in_flight
+                .pop_front()
+                .ok_or(Error::UnexpectedResponse(b))?;
+            s.stats.record_latency(acked.sent_at.elapsed());
+
+            match s.activity {
+                Activity::Calibrate => {
+                    // Resend frame 0 with final packet size to cleanly
+                    // separate calibration and send modes.
+                    s.activity = Activity::WaitResp;
+                    s.last_frame_sent = None;
+                    send_next_frame(s)?;
+                    fill_window(s)?;
+
+                    let loader = s.sfl_loader.as_ref().expect(
+                        "s.sfl_loader should have been initialized by Activity::LookForMagic",
+                    );
+                    Ok(Some(message_line(
+                        s,
+                        MessageLevel::Normal,
+                        format_args!("Using packet size: {}", loader.chunk_size),
+                    )))
                 }
-                err @ (Resp::CrcError | Resp::Unknown | Resp::AckError) => {
-                    redo_last_frame(s, err).map(|_| ReadAction::Swallow)
+                Activity::WaitResp => {
+                    // Resent frames can be acknowledged out of order, so
+                    // only count frames with nothing older still in flight.
+                    s.last_frame_acked = match s.in_flight.iter().filter_map(|f| f.num).min() {
+                        Some(oldest) => oldest.checked_sub(1),
+                        None => s.last_frame_sent,
+                    };
+                    fill_window(s)
                 }
+                _ => Ok(Some(finish_transfer(s))),
             }
         }
-        Activity::WaitFinalResp => {
-            match Resp::try_from(chunk[0]).map_err(|_| Error::UnexpectedResponse(chunk[0]))? {
-                Resp::Success => {
-                    let file_size = s.file_size.expect(
-                        "s.file_size should have been initialized by Activity::LookForMagic",
-                    ) as f64;
-
-                    s.file_size = None;
-                    s.last_frame_acked = None;
-                    s.last_frame_sent = None;
-                    s.activity = Activity::LookForMagic;
+        err @ (Resp::CrcError | Resp::Unknown | Resp::AckError) => {
+            s.stats.count_error(&err);
+            retry_oldest(s, err)
+        }
+    }
+}
 
-                    let elapsed = (Instant::now() - s.start_time.unwrap()).as_secs_f64();
-                    let rate = file_size / elapsed;
+/// Send the next `Load` frame, if there's any data left.
+fn send_next_frame(s: &mut State) -> Result<bool, Error> {
+    let next = s.last_frame_sent.map_or(0, |n| n + 1);
+    let loader = s
+        .sfl_loader
+        .as_mut()
+        .expect("s.sfl_loader should have been initialized by Activity::LookForMagic");
 
-                    let mut resp = String::new();
-                    let _ = write!(
-                        resp,
-                        "\r\n\x1B[0;36m[TTXLiteX] Done! ({}/s)\x1B[0m\r\n\r\n",
-                        pretty_bytes(rate as u64, Some(2))
-                    );
+    match loader
+        .encode_data_frame(next)
+        .map_err(|e| Error::FileIoError(e))?
+    {
+        Some(frame) => {
+            trace!("next: {:X?}", frame);
+            inject_output(s, frame.as_bytes())?;
+            s.in_flight.push_back(InFlight {
+                num: Some(next),
+                frame,
+                retries: 0,
+                sent_at: Instant::now(),
+            });
+            s.stats.frames_sent += 1;
+            s.last_frame_sent = Some(next);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
 
-                    Ok(ReadAction::Prepend(resp))
-                }
-                err @ (Resp::CrcError | Resp::Unknown | Resp::AckError) => {
-                    redo_last_frame(s, err).map(|_| ReadAction::Swallow)
-                }
+/// Keep as many frames in flight as the options allow. Once every `Load`
+/// is acknowledged, send the `Jump`, or finish if the receiver has none.
+fn fill_window(s: &mut State) -> Result<Option<String>, Error> {
+    while s.in_flight.len() < s.options.max_in_flight as usize {
+        if !send_next_frame(s)? {
+            break;
+        }
+    }
+
+    if !s.in_flight.is_empty() {
+        return Ok(None);
+    }
+
+    let upload = s
+        .upload
+        .as_ref()
+        .expect("s.upload should have been initialized by Activity::LookForMagic");
+    if upload.protocol.allows(Cmd::Jump) {
+        let addr = upload.jump_addr;
+        let magic = upload.protocol.magic.clone();
+        let loader = s
+            .sfl_loader
+            .as_mut()
+            .expect("s.sfl_loader should have been initialized by Activity::LookForMagic");
+        let frame = loader.encode_boot_frame(addr);
+        trace!("final: {:X?}", frame);
+        inject_output(s, frame.as_bytes())?;
+        s.in_flight.push_back(InFlight {
+            num: None,
+            frame,
+            retries: 0,
+            sent_at: Instant::now(),
+        });
+        s.stats.frames_sent += 1;
+        s.activity = Activity::WaitFinalResp;
+        s.jump_watch = JumpWatch::new(&s.options.boot_pattern, &magic);
+        Ok(Some(verbose(s, format_args!("Jumping to {:#010x}", addr))))
+    } else {
+        // Receiver has no Jump command; the last Load ack ends the
+        // transfer.
+        let mut resp = if s.options.status_bar {
+            status_bar(s)
+        } else {
+            String::new()
+        };
+        resp.push_str(&finish_transfer(s));
+        Ok(Some(resp))
+    }
+}
+
+/// The oldest frame in flight was rejected or timed out. During
+/// calibration, try a smaller chunk size; otherwise resend it, up to the
+/// retry limit.
+fn retry_oldest(s: &mut State, why: impl fmt::Display) -> Result<Option<String>, Error> {
+    let Some(mut oldest) = s.in_flight.pop_front() else {
+        return Ok(None);
+    };
+
+    let loader = s
+        .sfl_loader
+        .as_mut()
+        .expect("s.sfl_loader should have been initialized by Activity::LookForMagic");
+    if s.activity == Activity::Calibrate && loader.chunk_size > MIN_CHUNK_SIZE {
+        info!(target: "drive_sfl", "{}; halved packet size.", why);
+        loader.halve_chunk_size();
+        let chunk_size = loader.chunk_size;
+        s.stats.halvings += 1;
+        s.last_frame_sent = None;
+        send_next_frame(s)?;
+        return Ok(Some(verbose(
+            s,
+            format_args!("{}; trying packet size {}", why, chunk_size),
+        )));
+    }
+
+    oldest.retries += 1;
+    s.stats.frames_resent += 1;
+    let what = match oldest.num {
+        Some(n) => format!("frame {}", n),
+        None => "the Jump frame".to_string(),
+    };
+    if s.options
+        .max_retries
+        .is_some_and(|max| oldest.retries > max)
+    {
+        let msg = format!("{} failed {} times ({}).", what, oldest.retries, why);
+        return Ok(Some(abort_transfer(s, &msg)));
+    }
+
+    info!(target: "drive_sfl", "SFL Error: {}, resending current", why);
+    trace!("resend: {:X?}", oldest.frame);
+    inject_output(s, oldest.frame.as_bytes())?;
+    oldest.sent_at = Instant::now();
+    s.stats.frames_sent += 1;
+    s.in_flight.push_back(oldest);
+
+    Ok(Some(verbose(s, format_args!("Resent {} ({})", what, why))))
+}
+
+/// A message shown only at the verbose level. These come mid-transfer, so
+/// they overwrite the progress line, which is redrawn below them.
+fn verbose<T>(s: &State, msg: T) -> String
+where
+    T: fmt::Display,
+{
+    let line = message_line(s, MessageLevel::Verbose, msg);
+    if line.is_empty() {
+        return line;
+    }
+
+    format!("\r{}", line)
+}
+
+/// Give up on the transfer, telling the receiver to do the same if it can,
+/// and go back to waiting for the magic string.
+pub fn abort_transfer(s: &mut State, why: &str) -> String {
+    error!(target: "drive_sfl", "Aborting upload: {}", why);
+    save_resume_point(s);
+
+    if s.upload
+        .as_ref()
+        .is_some_and(|u| u.protocol.allows(Cmd::Abort))
+    {
+        if let Some(loader) = s.sfl_loader.as_mut() {
+            let frame = loader.encode_abort_frame();
+            let _ = inject_output(s, frame.as_bytes())
+                .inspect_err(|e| error!(target: "drive_sfl", "Could not send Abort: {}", e));
+        }
+    }
+    let report = report(s);
+
+    s.file_size = None;
+    s.last_frame_acked = None;
+    s.last_frame_sent = None;
+    s.in_flight.clear();
+    s.forget_approval();
+    s.activity = Activity::LookForMagic;
+    restart_timer(s);
+
+    outcome(s, format_args!("Upload aborted: {}", why), &report)
+}
+
+/// The receiver sent the magic string mid-transfer, so it was reset and
+/// isn't expecting any more frames. Go back to waiting for the magic string.
+fn receiver_restarted(s: &mut State) -> String {
+    warn!(target: "drive_sfl", "Receiver restarted mid-upload.");
+    save_resume_point(s);
+    let report = report(s);
+    let at = match s.last_frame_acked {
+        Some(n) => format!("after frame {}", n),
+        None => "before the first frame".to_string(),
+    };
+
+    s.file_size = None;
+    s.last_frame_acked = None;
+    s.last_frame_sent = None;
+    s.in_flight.clear();
+    s.activity = Activity::LookForMagic;
+    restart_timer(s);
+
+    outcome(
+        s,
+        format_args!("Receiver restarted {}; upload abandoned", at),
+        &report,
+    )
+}
+
+const FRAME_TIMER_ID: usize = 0x11_7E_C5;
+
+/// (Re)start the per-frame timeout while frames are in flight, and stop it
+/// otherwise.
+fn restart_timer(s: &State) {
+    if s.cv.is_null() {
+        return;
+    }
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let hwnd = unsafe { (*s.cv).HWin };
+    match s.options.frame_timeout {
+        Some(timeout) if !s.in_flight.is_empty() => unsafe {
+            SetTimer(
+                Some(hwnd),
+                FRAME_TIMER_ID,
+                timeout.as_millis().try_into().unwrap_or(u32::MAX),
+                Some(Some(on_frame_timeout)),
+            );
+        },
+        _ => unsafe {
+            let _ = KillTimer(Some(hwnd), FRAME_TIMER_ID);
+        },
+    }
+}
+
+unsafe extern "system" fn on_frame_timeout(_hwnd: HWND, _msg: u32, _id: usize, _time: u32) {
+    TTX_LITEX_STATE.with_borrow_mut(|s| {
+        if s.activity == Activity::WaitFinalResp && s.jump_watch.maybe_booted {
+            // Resending the Jump could land in the running firmware.
+            info!(target: "on_frame_timeout", "No Jump ack after a garbled reply.");
+            let text = jump_unacked(s, "but the firmware may be running");
+            s.notices.push_back(text.into());
+        } else if s.activity.in_transfer() {
+            s.stats.timeouts += 1;
+            match retry_oldest(s, "timed out") {
+                // Shown the next time Tera Term reads from the port.
+                Ok(Some(msg)) if !msg.is_empty() => s.notices.push_back(msg.into()),
+                Ok(_) => {}
+                Err(e) => error!(target: "on_frame_timeout", "Could not resend frame: {}", e),
             }
         }
+
+        restart_timer(s);
+    });
+}
+
+const TRIGGER_TIMER_ID: usize = 0x11_7E_C6;
+
+/// Arm the upload, and send the trigger to make the receiver ask for it.
+/// Going back to how things were if the magic string doesn't follow in
+/// time. Returns whether the trigger was sent.
+pub fn upload_now(s: &mut State) -> Result<bool, Error> {
+    if s.activity.in_transfer() {
+        notice(s, MessageLevel::Summary, "An upload is already running");
+        return Ok(false);
+    }
+    if s.filename.is_none() {
+        notice(
+            s,
+            MessageLevel::Summary,
+            "Choose a file to upload in the LiteX dialog first",
+        );
+        return Ok(false);
+    }
+
+    let fallback = s.trigger_fallback.unwrap_or(s.activity);
+    if s.activity == Activity::Inactive {
+        s.arm();
+        // Triggered by hand, so don't stay armed unless the user asked to be.
+        s.uploads_left = Some(1);
+    } else {
+        // Already armed; keep counting uploads from where we were.
+        s.matcher.reset();
+        s.restart_arm_expiry();
+    }
+    let trigger = s.options.trigger.clone();
+    inject_output(s, &trigger).inspect_err(|_| s.activity = fallback)?;
+    info!(target: "upload_now", "Sent trigger {:?}", profile::escape(&trigger));
+
+    s.trigger_fallback = Some(fallback);
+    if s.cv.is_null() {
+        return Ok(true);
+    }
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let hwnd = unsafe { (*s.cv).HWin };
+    match s.options.trigger_timeout {
+        Some(timeout) => unsafe {
+            SetTimer(
+                Some(hwnd),
+                TRIGGER_TIMER_ID,
+                timeout.as_millis().try_into().unwrap_or(u32::MAX),
+                Some(Some(on_trigger_timeout)),
+            );
+        },
+        None => unsafe {
+            let _ = KillTimer(Some(hwnd), TRIGGER_TIMER_ID);
+        },
+    }
+
+    Ok(true)
+}
+
+/// The magic string arrived, or never will; stop waiting for it.
+fn stop_trigger_timer(s: &mut State) {
+    if s.trigger_fallback.take().is_none() || s.cv.is_null() {
+        return;
+    }
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let hwnd = unsafe { (*s.cv).HWin };
+    unsafe {
+        let _ = KillTimer(Some(hwnd), TRIGGER_TIMER_ID);
+    }
+}
+
+unsafe extern "system" fn on_trigger_timeout(_hwnd: HWND, _msg: u32, _id: usize, _time: u32) {
+    TTX_LITEX_STATE.with_borrow_mut(|s| {
+        let Some(fallback) = s.trigger_fallback else {
+            return;
+        };
+        stop_trigger_timer(s);
+        if s.activity != Activity::LookForMagic {
+            return;
+        }
+
+        s.activity = fallback;
+        s.forget_approval();
+        let waited = s.options.trigger_timeout.unwrap_or_default();
+        let trigger = profile::escape(&s.options.trigger);
+        warn!(target: "on_trigger_timeout", "No magic string after the trigger");
+        // Shown the next time Tera Term reads from the port.
+        notice(
+            s,
+            MessageLevel::Summary,
+            format_args!(
+                "No upload request within {:.1}s of sending {}",
+                waited.as_secs_f64(),
+                trigger
+            ),
+        );
+    });
+}
+
+const CONFIRM_TIMER_ID: usize = 0x11_7E_C8;
+
+/// Ask whether to upload, from a timer rather than from the read hook, so
+/// that Tera Term keeps reading while the question is up.
+fn ask_to_confirm(s: &mut State) {
+    if s.cv.is_null() {
+        warn!(target: "drive_sfl", "No window to ask about the upload in.");
+        return;
+    }
+    s.confirming = true;
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let hwnd = unsafe { (*s.cv).HWin };
+    unsafe {
+        SetTimer(Some(hwnd), CONFIRM_TIMER_ID, 0, Some(Some(on_confirm)));
     }
 }
 
+unsafe extern "system" fn on_confirm(hwnd: HWND, _msg: u32, _id: usize, _time: u32) {
+    let _ = KillTimer(Some(hwnd), CONFIRM_TIMER_ID);
+
+    // Show exactly what would be sent, project config and all.
+    let plan = TTX_LITEX_STATE.with_borrow_mut(|s| {
+        Plan::new(s)
+            .inspect_err(|e| {
+                error!(target: "on_confirm", "Can't upload: {}", e);
+                notice(s, MessageLevel::Summary, e);
+            })
+            .ok()
+            .map(|plan| {
+                let question = format!(
+                    "The receiver is asking for an upload.\n\n{}\n\nUpload it?",
+                    plan.describe(s.region.as_deref())
+                );
+                (plan, question)
+            })
+    });
+
+    // Not borrowed while the question is up: Tera Term keeps reading.
+    let yes = plan
+        .as_ref()
+        .is_some_and(|(_, q)| ui::confirm_upload(hwnd, q));
+
+    TTX_LITEX_STATE.with_borrow_mut(|s| {
+        s.confirming = false;
+        if s.activity != Activity::LookForMagic {
+            return;
+        }
+
+        // Already said why not.
+        let Some((plan, _)) = plan else {
+            return;
+        };
+        if !yes {
+            info!(target: "on_confirm", "Upload declined.");
+            notice(s, MessageLevel::Summary, "Upload declined");
+            return;
+        }
+
+        // The receiver gave up waiting while we asked, so ask it again.
+        info!(target: "on_confirm", "Upload confirmed.");
+        match upload_now(s) {
+            Ok(true) => {
+                s.upload_approved = true;
+                s.approved_plan = Some(plan);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                error!(target: "on_confirm", "Could not send trigger: {}", e);
+                notice(s, MessageLevel::Summary, e);
+            }
+        }
+    });
+}
+
+const WATCH_TIMER_ID: usize = 0x11_7E_C7;
+
+/// Start or stop watching the file for new builds, to match the options.
+pub fn update_watch(s: &mut State) {
+    if s.cv.is_null() {
+        return;
+    }
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let hwnd = unsafe { (*s.cv).HWin };
+    if hwnd.is_invalid() {
+        return;
+    }
+
+    match s.filename.clone() {
+        Some(path) if s.options.watch_file => {
+            if s.watch.path() != Some(&path) {
+                info!(target: "update_watch", "Watching {}", path.display());
+            }
+            s.watch.watch(&path, Stamp::of(&path));
+            unsafe {
+                SetTimer(
+                    Some(hwnd),
+                    WATCH_TIMER_ID,
+                    watch::POLL_INTERVAL.as_millis() as u32,
+                    Some(Some(on_watch_tick)),
+                );
+            }
+        }
+        _ => {
+            s.watch.stop();
+            unsafe {
+                let _ = KillTimer(Some(hwnd), WATCH_TIMER_ID);
+            }
+        }
+    }
+}
+
+unsafe extern "system" fn on_watch_tick(_hwnd: HWND, _msg: u32, _id: usize, _time: u32) {
+    TTX_LITEX_STATE.with_borrow_mut(|s| {
+        // A build that lands mid-upload is picked up once it's over.
+        if s.activity.in_transfer() {
+            return;
+        }
+        let Some(path) = s.watch.path().map(|p| p.to_path_buf()) else {
+            return;
+        };
+        if !s.watch.poll(Stamp::of(&path), Instant::now()) {
+            return;
+        }
+
+        info!(target: "on_watch_tick", "New build of {}", path.display());
+        notice(
+            s,
+            MessageLevel::Normal,
+            format_args!("{} changed; asking for an upload", path.display()),
+        );
+        if let Err(e) = upload_now(s) {
+            error!(target: "on_watch_tick", "Could not send trigger: {}", e);
+            notice(s, MessageLevel::Summary, e);
+        }
+    });
+}
+
 fn inject_output(s: &mut State, buf: &[u8]) -> Result<(), Error> {
     // SAFETY: Assumes TeraTerm passed us valid pointers. We can't use
     // &mut because I have no idea whether we truly have exclusive access.
@@ -373,6 +1248,7 @@ ttx_export! {
             // SAFETY: Assumes TeraTerm passed us valid pointers.
             s.orig_readfile = *(*hooks).PReadFile;
             *(*hooks).PReadFile = Some(our_p_read_file);
+            update_watch(s);
 
             trace!(target: "TTXOpenFile", "s.orig_readfile <= {:?} ({:?})", &raw const s.orig_readfile, s.orig_readfile);
             trace!(target: "TTXOpenFile", "*(*hooks).PReadFile <= {:?}", our_p_read_file as * const ());
@@ -386,8 +1262,254 @@ ttx_export! {
             // SAFETY: Assumes TeraTerm passed us valid pointers, and that
             // TeraTerm calls this function _after_ TTXOpenFile.
             *(*hooks).PReadFile = s.orig_readfile;
+            s.watch.stop();
 
             trace!(target: "TTXCloseFile", "*(*hooks).PReadFile <= {:?}", *(*hooks).PReadFile);
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::{env, fs, mem, process};
+
+    use crate::sfl::MAGIC;
+    use crate::state::DEFAULT_ADDR;
+
+    /// What a LiteX board prints when it resets, up to the magic string.
+    const BANNER: &[u8] = b"\x1b[1m        __   _ __      _  __\x1b[0m\n\
+        \n BIOS CRC passed (8b4be9ac)\n\
+        \x1b[1mCPU\x1b[0m:\t\tVexRiscv @ 100MHz\n\
+        \x1b[1mSDRAM\x1b[0m:\t\t128.0MiB 16-bit @ 400MT/s (CL-6 CWL-5)\n\
+        Memtest at 0x40000000 (2.0MiB)...\nMemtest OK\n\
+        Memspeed at 0x40000000 (Sequential, 2.0MiB)...\n\
+        Booting from serial...\nPress Q or ESC to abort boot completely.\n";
+
+    /// An armed upload of `len` bytes, 16 per frame, talking to a fake
+    /// Tera Term.
+    struct Upload {
+        s: State,
+        /// `s.cv` points in here.
+        _cv: Box<tt::TComVar>,
+        path: PathBuf,
+    }
+
+    impl Upload {
+        fn new(name: &str, len: usize) -> Self {
+            let path = env::temp_dir().join(format!("ttxlitex-{}-{}.bin", name, process::id()));
+            fs::write(&path, vec![0x5a; len]).unwrap();
+
+            // SAFETY: TComVar is plain data, for which all zeroes is valid.
+            let mut cv: Box<tt::TComVar> = Box::new(unsafe { mem::zeroed() });
+            let mut s = State::new(ptr::null_mut(), &mut *cv);
+            s.filename = Some(path.clone());
+            s.options.chunk_size = Some(16);
+            s.options.status_bar = false;
+            s.arm();
+
+            Self { s, _cv: cv, path }
+        }
+
+        fn started(name: &str, len: usize, acks: usize) -> Self {
+            let mut upload = Self::new(name, len);
+            upload.start(acks);
+            upload
+        }
+
+        /// Answer the magic string, and have the receiver acknowledge
+        /// `acks` frames.
+        fn start(&mut self, acks: usize) {
+            self.read(MAGIC);
+            for _ in 0..acks {
+                self.read(b"K");
+            }
+        }
+
+        /// What [`our_p_read_file`] does with a read.
+        fn read(&mut self, chunk: &[u8]) -> ReadAction {
+            // What we send isn't checked; just keep OutBuff from filling up.
+            unsafe { (*self.s.cv).OutBuffCount = 0 };
+            watch_banner(&mut self.s, chunk);
+            drive_sfl(&mut self.s, chunk).unwrap()
+        }
+    }
+
+    impl Drop for Upload {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn restarted(action: &ReadAction) -> bool {
+        matches!(action, ReadAction::Splice { text, .. } if String::from_utf8_lossy(text).contains("Receiver restarted"))
+    }
+
+    #[test]
+    fn acks_drive_the_upload() {
+        let mut upload = Upload::started("acks", 64, 2);
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.last_frame_acked, Some(1));
+
+        upload.read(b"K");
+        upload.read(b"K");
+        assert_eq!(upload.s.activity, Activity::WaitFinalResp);
+        upload.read(b"K");
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+    }
+
+    #[test]
+    fn reset_during_wait_resp() {
+        let mut upload = Upload::started("wait-resp", 64, 1);
+
+        upload.read(BANNER);
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.last_frame_acked, Some(0));
+        assert_eq!(upload.s.in_flight.len(), 1);
+        assert_eq!(upload.s.stats.frames_resent, 0);
+
+        let action = upload.read(&MAGIC[..5]);
+        assert!(!restarted(&action));
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        let action = upload.read(&MAGIC[5..]);
+        assert!(restarted(&action));
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+        assert!(upload.s.in_flight.is_empty());
+    }
+
+    #[test]
+    fn reset_during_wait_final_resp() {
+        let mut upload = Upload::started("wait-final-resp", 16, 1);
+        assert_eq!(upload.s.activity, Activity::WaitFinalResp);
+
+        let mut chunk = BANNER.to_vec();
+        chunk.extend_from_slice(MAGIC);
+        let action = upload.read(&chunk);
+        assert!(restarted(&action));
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+        assert_eq!(upload.s.stats.frames_resent, 0);
+    }
+
+    #[test]
+    fn reset_split_across_reads_after_jump() {
+        let mut upload = Upload::started("split-after-jump", 16, 1);
+
+        let mut chunk = b"\r\n".to_vec();
+        chunk.extend_from_slice(&MAGIC[..5]);
+        upload.read(&chunk);
+        assert_eq!(upload.s.activity, Activity::WaitFinalResp);
+        assert!(restarted(&upload.read(&MAGIC[5..])));
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+    }
+
+    #[test]
+    fn output_passes_through() {
+        let mut upload = Upload::started("output", 64, 1);
+
+        // Latin-1 and UTF-8, as the terminal is set up to show them.
+        let output = b"Temp: 25\xb0C, \xc2\xb5C ready\r\n";
+        let ReadAction::Replace(text) = upload.read(output) else {
+            panic!("output swallowed");
+        };
+        assert_eq!(text, output);
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+    }
+
+    #[test]
+    fn garbled_jump_ack_waits() {
+        let mut upload = Upload::started("garbled", 16, 1);
+
+        assert!(matches!(upload.read(b"\xeb"), ReadAction::Swallow));
+        assert_eq!(upload.s.activity, Activity::WaitFinalResp);
+        assert!(upload.s.jump_watch.maybe_booted);
+
+        upload.read(b"Hello from firmware\r\n");
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+    }
+
+    #[test]
+    fn ack_after_garbled_byte() {
+        let mut upload = Upload::started("garbled-then-ack", 16, 1);
+
+        upload.read(b"\xeb");
+        upload.read(b"K");
+        assert_eq!(upload.s.activity, Activity::LookForMagic);
+    }
+
+    /// An upload that resumes when the receiver restarts, after two frames.
+    fn resumable(name: &str) -> Upload {
+        let mut upload = Upload::new(name, 64);
+        upload.s.options.resume_uploads = true;
+        upload.s.options.restart_on_reset = true;
+        upload.start(2);
+        upload
+    }
+
+    #[test]
+    fn restart_resumes_upload() {
+        let mut upload = resumable("resume");
+
+        assert!(restarted(&upload.read(MAGIC)));
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.last_frame_acked, Some(1));
+        assert_eq!(upload.s.last_frame_sent, Some(2));
+    }
+
+    #[test]
+    fn reboot_mid_upload_starts_over() {
+        let mut upload = resumable("reboot");
+
+        upload.read(BANNER);
+        assert!(restarted(&upload.read(MAGIC)));
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.last_frame_acked, None);
+        assert_eq!(upload.s.last_frame_sent, Some(0));
+        assert!(upload.s.resume.is_none());
+    }
+
+    #[test]
+    fn reset_restarts_upload() {
+        let mut upload = Upload::started("restart", 64, 2);
+        upload.s.options.restart_on_reset = true;
+
+        let mut chunk = BANNER.to_vec();
+        chunk.extend_from_slice(MAGIC);
+        assert!(restarted(&upload.read(&chunk)));
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.last_frame_sent, Some(0));
+        assert_eq!(upload.s.last_frame_acked, None);
+    }
+
+    #[test]
+    fn approved_plan_is_sent_as_is() {
+        let mut upload = Upload::new("approved", 16);
+        upload.s.options.confirm_upload = true;
+        let plan = Plan::new(&upload.s).unwrap();
+        upload.s.upload_approved = true;
+        upload.s.approved_plan = Some(plan);
+
+        // Changed after the user said yes.
+        upload.s.addr = 0x1000_0000;
+        upload.start(0);
+        assert_eq!(upload.s.activity, Activity::WaitResp);
+        assert_eq!(upload.s.upload.as_ref().map(|u| u.addr), Some(DEFAULT_ADDR));
+        assert!(upload.s.approved_plan.is_none());
+    }
+
+    #[test]
+    fn overflow_is_deferred() {
+        let mut upload = Upload::new("overflow", 16);
+        let s = &mut upload.s;
+        s.notices.push_back(b"later".to_vec());
+
+        let shown = defer_overflow(s, b"0123456789".to_vec(), 3, 4);
+        assert_eq!(shown, b"012");
+        assert_eq!(s.notices, [&b"3456"[..], b"789", b"later"]);
+
+        // No room left at all.
+        s.notices.clear();
+        assert!(defer_overflow(s, b"0123".to_vec(), 0, 4).is_empty());
+        assert_eq!(s.notices, [b"0123"]);
+    }
+}
@@ -2,22 +2,32 @@
 
 #[macro_use]
 mod teraterm; // Order matters: https://stackoverflow.com/a/29069165
+mod banner;
+mod board;
+mod csr;
 mod error;
+mod expr;
+mod history;
 mod io;
+mod options;
+mod plan;
+mod progress;
+mod progress_window;
+mod project;
+mod setup;
 mod sfl;
 mod state;
+mod stats;
+mod summary;
 mod ui;
+mod watch;
 
 use std::env;
-use std::ffi::OsString;
-use std::fs::File;
-use std::path::PathBuf;
 
 use error::Error;
 use log::*;
-use parse_int::parse;
-use sfl::{MagicMatcher, SflLoader};
-use state::{Activity, State, OUR_HINST, TTX_LITEX_STATE};
+use options::MessageLevel;
+use state::{State, OUR_HINST, TTX_LITEX_STATE};
 use teraterm as tt;
 
 use windows::Win32::Foundation::*;
@@ -28,12 +38,12 @@ const TTX_EXPORTS: tt::TTXExports = tt::TTXExports {
     loadOrder: 4600,
     TTXInit: Some(ttx_init),
     TTXGetUIHooks: None,
-    TTXGetSetupHooks: None,
+    TTXGetSetupHooks: Some(setup::ttx_get_setup_hooks),
     TTXOpenTCP: None,
     TTXCloseTCP: None,
     TTXSetWinSize: None,
     TTXModifyMenu: Some(ui::ttx_modify_menu),
-    TTXModifyPopupMenu: None,
+    TTXModifyPopupMenu: Some(ui::ttx_modify_popup_menu),
     TTXProcessCommand: Some(ui::ttx_process_command),
     TTXEnd: None,
     TTXSetCommandLine: None,
@@ -65,61 +75,36 @@ unsafe extern "system" fn ttx_bind(_version: tt::WORD, exports: *mut tt::TTXExpo
 
 ttx_export! {
     unsafe fn ttx_init(ts: tt::PTTSet, cv: tt::PComVar) {
-        if cfg!(debug_assertions) {
-            let _ = stderrlog::new().verbosity(log::Level::Trace).init();
-        } else {
-            let _ = stderrlog::new().quiet(true).init();
+        let mut errors = Vec::new();
+        let default_level = if cfg!(debug_assertions) { Some(Level::Trace) } else { None };
+
+        match setup::env_log_level(&mut errors).unwrap_or(default_level) {
+            Some(level) => {
+                let _ = stderrlog::new().verbosity(level).init();
+            }
+            None => {
+                let _ = stderrlog::new().quiet(true).init();
+            }
         }
 
-        let mut filename: Option<PathBuf> = None;
-        let mut activity: Activity = Activity::Inactive;
-        let mut addr = 0x40000000;
-        let mut sfl_loader: Option<SflLoader<File>> = None;
+        let mut state = State::new(ts, cv);
+        state.env_overrides = setup::env_overrides(&mut errors);
+        debug!(target: "TTXInit", "TTX_LITEX_* overrides: {:?} {:?}", state.env_overrides, env::current_dir());
 
-        if cfg!(debug_assertions) {
-            if let Ok(f) = env::var("TTX_LITEX_KERNEL") {
-                debug!(target: "TTXInit", "Found TTX_LITEX_KERNEL override: {} {:?}", f, env::current_dir());
-                addr = env::var("TTX_LITEX_ADDRESS")
-                    .inspect_err(|e| error!(target: "TTXInit", "{}", e))
-                    .ok()
-                    .and_then(|s| {
-                        parse::<u32>(&s)
-                            .inspect_err(|e| error!(target: "TTXInit", "{}", e))
-                            .ok()
-                    })
-                    .unwrap_or(addr);
-                debug!(target: "TTXInit", "Address is {:#08X}", addr);
+        let env_overrides = state.env_overrides.clone();
+        if let Err(e) = setup::apply_overrides(&env_overrides, &mut state) {
+            errors.push(e);
+        }
 
-                let path = PathBuf::from(OsString::from(f));
-                match SflLoader::open(path.clone(), addr) {
-                    Ok(ldr) => {
-                        debug!(target: "TTXInit", "Forcing TTXLiteX directly into LookForMagic state");
-                        filename = Some(path);
-                        activity = Activity::LookForMagic;
-                        sfl_loader = Some(ldr);
-                    }
-                    Err(e) => {
-                        error!(target: "TTXInit", "Could not force TTXLiteX into LookForMagic state: {}", e);
-                    }
-                }
-            }
+        for e in errors {
+            error!(target: "TTXInit", "Bad TTX_LITEX_* environment variable: {}", e);
+            io::notice(
+                &mut state,
+                MessageLevel::Summary,
+                format_args!("Bad TTX_LITEX_* environment variable: {}", e));
         }
 
-        TTX_LITEX_STATE.set(State {
-            ts,
-            cv,
-            orig_readfile: None,
-            activity,
-            matcher: MagicMatcher::new(sfl::MAGIC),
-            sfl_loader,
-            last_frame_acked: None,
-            last_frame_sent: None,
-            filename,
-            addr,
-            curr_frame: None,
-            file_size: None,
-            start_time: None
-        });
+        TTX_LITEX_STATE.set(state);
     }
 }
 
@@ -0,0 +1,530 @@
+/*! Advanced transfer options.
+
+These tune how the plugin drives an SFL transfer, as opposed to the
+[protocol profile](super::sfl::Profile), which describes what the receiver
+understands. The defaults match how the plugin has always behaved. */
+
+use std::time::Duration;
+
+use log::*;
+
+use super::progress::StatusStyle;
+use super::setup::Ini;
+use super::sfl::profile;
+use super::Error;
+
+/// Largest chunk size `SflLoader` supports.
+pub const MAX_CHUNK_SIZE: u16 = 251;
+/// Most frames that may be sent ahead of their acknowledgments.
+pub const MAX_IN_FLIGHT: u32 = 64;
+/// Triggers offered in the dialog. The first is the default.
+pub const TRIGGERS: &[&[u8]] = &[b"serialboot\n", b"reboot\n"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferOptions {
+    /// Bytes of data per `Load` frame, or `None` to calibrate: start at
+    /// the protocol's largest chunk, and halve it (down to
+    /// [`MIN_CHUNK_SIZE`](super::sfl::MIN_CHUNK_SIZE)) until the receiver
+    /// accepts a frame.
+    pub chunk_size: Option<u16>,
+    /// Frames sent before waiting for the first acknowledgment. More than 1
+    /// is faster, but only works if the receiver can buffer that much.
+    pub max_in_flight: u32,
+    /// Resend a frame if it isn't acknowledged in time. `None` waits
+    /// forever.
+    pub frame_timeout: Option<Duration>,
+    /// Give up on the transfer after resending a frame this many times.
+    /// `None` keeps trying.
+    pub max_retries: Option<u32>,
+    /// Draw a progress bar in the terminal during the transfer.
+    pub status_bar: bool,
+    pub status_style: StatusStyle,
+    /// Open a window showing the transfer's progress, with a Cancel button.
+    pub progress_window: bool,
+    /// Report statistics about the transfer when it ends, rather than just
+    /// the average rate.
+    pub detailed_report: bool,
+    /// Which messages the plugin writes into the terminal.
+    pub message_level: MessageLevel,
+    /// Color of those messages, or `None` for the terminal's default.
+    pub message_color: Option<Color>,
+    /// Switch to the start of main RAM, as reported by the LiteX BIOS's
+    /// Memtest line, whenever the BIOS boots. Otherwise it's only
+    /// mentioned.
+    pub detect_boot_addr: bool,
+    /// Sent by "Upload Now" to make the receiver ask for an upload.
+    pub trigger: Vec<u8>,
+    /// Give up on "Upload Now" if the magic string doesn't follow the
+    /// trigger in time. `None` waits forever.
+    pub trigger_timeout: Option<Duration>,
+    /// Send the trigger whenever a new build of the file settles, so it's
+    /// uploaded without touching the board.
+    pub watch_file: bool,
+    /// How many uploads the plugin stays armed for.
+    pub arm_mode: ArmMode,
+    /// Disarm if no upload starts this long after arming (or after the last
+    /// upload). `None` stays armed indefinitely.
+    pub arm_expiry: Option<Duration>,
+    /// Ask before answering the magic string, showing what would be
+    /// uploaded where.
+    pub confirm_upload: bool,
+    /// Start the upload over if the receiver restarts partway through it.
+    /// Otherwise the plugin waits for the next magic string.
+    pub restart_on_reset: bool,
+    /// When an upload is interrupted, carry on from the last acknowledged
+    /// frame next time, if the images and addresses haven't changed.
+    pub resume_uploads: bool,
+    /// Output that shows the firmware has started, in case the Jump
+    /// frame's acknowledgment is lost. Empty relies on any printable
+    /// output instead.
+    pub boot_pattern: Vec<u8>,
+}
+
+/// How long the plugin stays armed once an upload succeeds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArmMode {
+    /// Disarm after one successful upload.
+    Once,
+    /// Disarm after this many successful uploads.
+    Times(u32),
+    /// Stay armed, re-uploading whenever the receiver asks.
+    Always,
+}
+
+impl ArmMode {
+    pub fn name(&self) -> String {
+        match self {
+            ArmMode::Once => "once".to_string(),
+            ArmMode::Times(n) => n.to_string(),
+            ArmMode::Always => "always".to_string(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("once") {
+            Some(ArmMode::Once)
+        } else if name.eq_ignore_ascii_case("always") {
+            Some(ArmMode::Always)
+        } else {
+            match name.trim().parse::<u32>().ok()? {
+                0 => None,
+                1 => Some(ArmMode::Once),
+                n => Some(ArmMode::Times(n)),
+            }
+        }
+    }
+
+    /// Successful uploads before disarming, or `None` for no limit.
+    pub fn uploads(&self) -> Option<u32> {
+        match self {
+            ArmMode::Once => Some(1),
+            ArmMode::Times(n) => Some(*n),
+            ArmMode::Always => None,
+        }
+    }
+}
+
+/// How much the plugin writes into the terminal. Each level includes the
+/// ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum MessageLevel {
+    /// Nothing at all, for sessions that are logged and parsed.
+    Silent,
+    /// Only how each upload ended, and problems that stop one starting.
+    Summary,
+    /// Also when an upload starts, and the progress line.
+    Normal,
+    /// Also each resend, and each step of calibration.
+    Verbose,
+}
+
+impl MessageLevel {
+    pub const ALL: [Self; 4] = [
+        MessageLevel::Silent,
+        MessageLevel::Summary,
+        MessageLevel::Normal,
+        MessageLevel::Verbose,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageLevel::Silent => "silent",
+            MessageLevel::Summary => "summary",
+            MessageLevel::Normal => "normal",
+            MessageLevel::Verbose => "verbose",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|l| l.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The eight basic ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl Color {
+    pub const ALL: [Self; 8] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::White,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Color::Black => "black",
+            Color::Red => "red",
+            Color::Green => "green",
+            Color::Yellow => "yellow",
+            Color::Blue => "blue",
+            Color::Magenta => "magenta",
+            Color::Cyan => "cyan",
+            Color::White => "white",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+
+    /// SGR parameter that selects this as the foreground color.
+    pub fn sgr(&self) -> u8 {
+        30 + *self as u8
+    }
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: None,
+            max_in_flight: 1,
+            frame_timeout: None,
+            max_retries: None,
+            status_bar: true,
+            status_style: StatusStyle::Full,
+            progress_window: false,
+            detailed_report: false,
+            message_level: MessageLevel::Normal,
+            message_color: Some(Color::Cyan),
+            detect_boot_addr: false,
+            trigger: TRIGGERS[0].to_vec(),
+            trigger_timeout: Some(Duration::from_secs(10)),
+            watch_file: false,
+            arm_mode: ArmMode::Always,
+            arm_expiry: None,
+            confirm_upload: false,
+            restart_on_reset: false,
+            resume_uploads: false,
+            boot_pattern: Vec::new(),
+        }
+    }
+}
+
+impl TransferOptions {
+    /// Chunk size the loader starts at, for a protocol whose largest chunk
+    /// is `protocol_max`.
+    pub fn initial_chunk_size(&self, protocol_max: u16) -> u16 {
+        self.chunk_size
+            .map_or(protocol_max, |c| c.min(protocol_max))
+    }
+
+    pub fn calibrates(&self) -> bool {
+        self.chunk_size.is_none()
+    }
+
+    pub fn load(ini: &Ini) -> Self {
+        let mut opts = Self::default();
+
+        match ini.get("ChunkSize") {
+            Some(c) if c.eq_ignore_ascii_case("auto") => opts.chunk_size = None,
+            Some(_) => {
+                opts.chunk_size = ini
+                    .get_u32("ChunkSize")
+                    .and_then(|c| u16::try_from(c).ok())
+                    .filter(|c| (1..=MAX_CHUNK_SIZE).contains(c));
+            }
+            None => {}
+        }
+
+        if let Some(n) = ini
+            .get_u32("MaxInFlight")
+            .filter(|n| (1..=MAX_IN_FLIGHT).contains(n))
+        {
+            opts.max_in_flight = n;
+        }
+
+        // 0 means "no limit" for both of these.
+        opts.frame_timeout = ini
+            .get_u32("FrameTimeout")
+            .filter(|t| *t != 0)
+            .map(|t| Duration::from_millis(t.into()));
+        opts.max_retries = ini.get_u32("MaxRetries").filter(|r| *r != 0);
+        if opts.max_in_flight > 1 && opts.frame_timeout.is_some() {
+            warn!(target: "options", "Ignoring FrameTimeout with MaxInFlight > 1");
+            opts.frame_timeout = None;
+        }
+
+        if let Some(b) = ini.get_bool("StatusBar") {
+            opts.status_bar = b;
+        }
+        if let Some(style) = ini
+            .get("StatusStyle")
+            .and_then(|s| StatusStyle::from_name(&s))
+        {
+            opts.status_style = style;
+        }
+        if let Some(b) = ini.get_bool("ProgressWindow") {
+            opts.progress_window = b;
+        }
+        if let Some(b) = ini.get_bool("DetailedReport") {
+            opts.detailed_report = b;
+        }
+        if let Some(level) = ini
+            .get("MessageLevel")
+            .and_then(|l| MessageLevel::from_name(&l))
+        {
+            opts.message_level = level;
+        }
+        match ini.get("MessageColor") {
+            Some(c) if c.eq_ignore_ascii_case("none") => opts.message_color = None,
+            Some(c) => {
+                if let Some(color) = Color::from_name(&c) {
+                    opts.message_color = Some(color);
+                }
+            }
+            None => {}
+        }
+        if let Some(b) = ini.get_bool("DetectBootAddress") {
+            opts.detect_boot_addr = b;
+        }
+        if let Some(trigger) = ini.get("Trigger").and_then(|t| profile::unescape(&t)) {
+            opts.trigger = trigger;
+        }
+        if let Some(t) = ini.get_u32("TriggerTimeout") {
+            opts.trigger_timeout = (t != 0).then(|| Duration::from_millis(t.into()));
+        }
+        if let Some(b) = ini.get_bool("WatchFile") {
+            opts.watch_file = b;
+        }
+        if let Some(mode) = ini.get("ArmMode").and_then(|m| ArmMode::from_name(&m)) {
+            opts.arm_mode = mode;
+        }
+        if let Some(m) = ini.get_u32("ArmExpiry") {
+            opts.arm_expiry = (m != 0).then(|| Duration::from_secs(u64::from(m) * 60));
+        }
+        if let Some(b) = ini.get_bool("ConfirmUpload") {
+            opts.confirm_upload = b;
+        }
+        if let Some(b) = ini.get_bool("RestartOnReset") {
+            opts.restart_on_reset = b;
+        }
+        if let Some(b) = ini.get_bool("ResumeUploads") {
+            opts.resume_uploads = b;
+        }
+        if let Some(pattern) = ini.get("BootPattern").and_then(|p| profile::unescape(&p)) {
+            opts.boot_pattern = pattern;
+        }
+
+        opts
+    }
+
+    pub fn save(&self, ini: &Ini) -> Result<(), Error> {
+        ini.set(
+            "ChunkSize",
+            &self
+                .chunk_size
+                .map_or("auto".to_string(), |c| c.to_string()),
+        )?;
+        ini.set("MaxInFlight", &self.max_in_flight.to_string())?;
+        ini.set(
+            "FrameTimeout",
+            &self.frame_timeout.map_or(0, |t| t.as_millis()).to_string(),
+        )?;
+        ini.set("MaxRetries", &self.max_retries.unwrap_or(0).to_string())?;
+        ini.set_bool("StatusBar", self.status_bar)?;
+        ini.set("StatusStyle", self.status_style.name())?;
+        ini.set_bool("ProgressWindow", self.progress_window)?;
+        ini.set_bool("DetailedReport", self.detailed_report)?;
+        ini.set("MessageLevel", self.message_level.name())?;
+        ini.set(
+            "MessageColor",
+            self.message_color.map_or("none", |c| c.name()),
+        )?;
+        ini.set_bool("DetectBootAddress", self.detect_boot_addr)?;
+        ini.set("Trigger", &profile::escape(&self.trigger))?;
+        ini.set(
+            "TriggerTimeout",
+            &self
+                .trigger_timeout
+                .map_or(0, |t| t.as_millis())
+                .to_string(),
+        )?;
+        ini.set_bool("WatchFile", self.watch_file)?;
+        ini.set("ArmMode", &self.arm_mode.name())?;
+        ini.set(
+            "ArmExpiry",
+            &self.arm_expiry.map_or(0, |t| t.as_secs() / 60).to_string(),
+        )?;
+        ini.set_bool("ConfirmUpload", self.confirm_upload)?;
+        ini.set_bool("RestartOnReset", self.restart_on_reset)?;
+        ini.set_bool("ResumeUploads", self.resume_uploads)?;
+        ini.set("BootPattern", &profile::escape(&self.boot_pattern))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::tests::TempIni;
+    use crate::setup::SECTION;
+
+    #[test]
+    fn defaults_without_settings() {
+        let file = TempIni::new("options-empty");
+        assert_eq!(
+            TransferOptions::load(&file.section(SECTION)),
+            TransferOptions::default()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempIni::new("options");
+        let ini = file.section(SECTION);
+
+        let opts = TransferOptions {
+            chunk_size: Some(128),
+            max_in_flight: 1,
+            frame_timeout: Some(Duration::from_millis(250)),
+            max_retries: Some(5),
+            status_bar: false,
+            status_style: StatusStyle::Compact,
+            progress_window: true,
+            detailed_report: true,
+            message_level: MessageLevel::Verbose,
+            message_color: None,
+            detect_boot_addr: true,
+            trigger: b"reboot\r\n".to_vec(),
+            trigger_timeout: None,
+            watch_file: true,
+            arm_mode: ArmMode::Times(3),
+            arm_expiry: Some(Duration::from_secs(30 * 60)),
+            confirm_upload: true,
+            restart_on_reset: true,
+            resume_uploads: true,
+            boot_pattern: b"\x1b[1mhello".to_vec(),
+        };
+        opts.save(&ini).unwrap();
+        assert_eq!(TransferOptions::load(&ini), opts);
+
+        let opts = TransferOptions {
+            max_in_flight: MAX_IN_FLIGHT,
+            frame_timeout: None,
+            ..TransferOptions::default()
+        };
+        opts.save(&ini).unwrap();
+        assert_eq!(TransferOptions::load(&ini), opts);
+    }
+
+    #[test]
+    fn bad_values_are_ignored() {
+        let file = TempIni::new("options-bad");
+        let ini = file.section(SECTION);
+        for (key, value) in [
+            ("MaxInFlight", "0"),
+            ("StatusBar", "sometimes"),
+            ("StatusStyle", "fancy"),
+            ("MessageLevel", "loud"),
+            ("MessageColor", "mauve"),
+            ("Trigger", r"\xZZ"),
+            ("ArmMode", "0"),
+            ("TriggerTimeout", "soon"),
+        ] {
+            ini.set(key, value).unwrap();
+        }
+        assert_eq!(TransferOptions::load(&ini), TransferOptions::default());
+
+        for chunk_size in ["0", "252", "big"] {
+            ini.set("ChunkSize", chunk_size).unwrap();
+            assert_eq!(
+                TransferOptions::load(&ini).chunk_size,
+                None,
+                "{}",
+                chunk_size
+            );
+        }
+        ini.set("ChunkSize", "Auto").unwrap();
+        assert!(TransferOptions::load(&ini).calibrates());
+
+        ini.set("MaxInFlight", "65").unwrap();
+        assert_eq!(TransferOptions::load(&ini).max_in_flight, 1);
+    }
+
+    #[test]
+    fn frame_timeout_needs_one_in_flight() {
+        let file = TempIni::new("options-timeout");
+        let ini = file.section(SECTION);
+        ini.set("FrameTimeout", "500").unwrap();
+        ini.set("MaxInFlight", "4").unwrap();
+
+        let opts = TransferOptions::load(&ini);
+        assert_eq!(opts.max_in_flight, 4);
+        assert_eq!(opts.frame_timeout, None);
+
+        ini.set("MaxInFlight", "1").unwrap();
+        assert_eq!(
+            TransferOptions::load(&ini).frame_timeout,
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn arm_modes() {
+        for mode in [ArmMode::Once, ArmMode::Times(5), ArmMode::Always] {
+            assert_eq!(ArmMode::from_name(&mode.name()), Some(mode));
+        }
+        assert_eq!(ArmMode::from_name("ALWAYS"), Some(ArmMode::Always));
+        assert_eq!(ArmMode::from_name("1"), Some(ArmMode::Once));
+        assert_eq!(ArmMode::from_name("0"), None);
+        assert_eq!(ArmMode::from_name("twice"), None);
+        assert_eq!(ArmMode::Times(5).uploads(), Some(5));
+        assert_eq!(ArmMode::Always.uploads(), None);
+    }
+
+    #[test]
+    fn names() {
+        for level in MessageLevel::ALL {
+            assert_eq!(MessageLevel::from_name(level.name()), Some(level));
+        }
+        for color in Color::ALL {
+            assert_eq!(Color::from_name(color.name()), Some(color));
+        }
+        assert_eq!(
+            MessageLevel::from_name("Verbose"),
+            Some(MessageLevel::Verbose)
+        );
+        assert_eq!(Color::from_name("none"), None);
+        assert_eq!(Color::Cyan.sgr(), 36);
+    }
+}
@@ -0,0 +1,119 @@
+/*! What an upload will send, worked out before it starts.
+
+When the user is asked whether to upload, they're shown a plan, and the
+upload that follows their answer sends exactly that plan rather than looking
+the project config up again. */
+
+use std::fmt::Write;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use pretty_bytes_typed::pretty_bytes;
+
+use super::project::{ProjectConfig, UploadSettings};
+use super::sfl::{self, Cmd, SflLoader};
+use super::state::State;
+use super::watch;
+use super::Error;
+
+pub struct Plan {
+    pub settings: UploadSettings,
+    pub loader: SflLoader<File>,
+    /// Path, address and length of every image, in upload order.
+    pub images: Vec<(PathBuf, u32, u64)>,
+    /// Where the settings came from, if not just the dialog.
+    pub config: Option<ProjectConfig>,
+}
+
+impl Plan {
+    /// Work out what uploading the file chosen in `s` would send.
+    pub fn new(s: &State) -> Result<Self, Error> {
+        let filename = s.filename.clone().ok_or(Error::FileIoError(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no file to upload",
+        )))?;
+
+        let config = ProjectConfig::discover(&filename)?;
+        let settings = UploadSettings::new(s, config.as_ref())?;
+
+        let chunk_size = s
+            .options
+            .initial_chunk_size(settings.protocol.max_chunk_size());
+        let mut loader = SflLoader::open(&filename, settings.addr)
+            .map_err(Error::FileIoError)?
+            .max_chunk_size(chunk_size);
+        let mut paths = vec![filename];
+        for (path, addr) in config.iter().flat_map(|c| c.image_paths()) {
+            loader.add_file(&path, addr).map_err(Error::FileIoError)?;
+            paths.push(path);
+        }
+
+        let images: Vec<_> = paths
+            .into_iter()
+            .zip(loader.images().map_err(Error::FileIoError)?)
+            .map(|(path, (addr, len))| (path, addr, len))
+            .collect();
+        if let Some((path, addr, len)) = images.iter().find(|(_, a, l)| !sfl::image_fits(*a, *l)) {
+            return Err(Error::BadAddressError {
+                addr: format!("{:#010x}", addr),
+                why: format!(
+                    "{} ({} bytes) would run past the end of memory",
+                    path.display(),
+                    len
+                ),
+            });
+        }
+        if images[0].2 == 0 {
+            return Err(Error::FileIoError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input file was empty",
+            )));
+        }
+
+        Ok(Self {
+            settings,
+            loader,
+            images,
+            config,
+        })
+    }
+
+    /// Every image and address the upload sends, for asking the user about
+    /// it. `region` is the memory region the first image was meant for.
+    pub fn describe(&self, region: Option<&str>) -> String {
+        let mut out = String::new();
+
+        let (path, addr, _) = &self.images[0];
+        let build =
+            watch::describe_build(path).unwrap_or_else(|e| format!("{} ({})", path.display(), e));
+        let _ = write!(
+            out,
+            "File: {}\nPath: {}\nAddress: {:#010x}",
+            build,
+            path.display(),
+            addr
+        );
+        if let Some(region) = region {
+            let _ = write!(out, " ({})", region);
+        }
+
+        for (path, addr, len) in &self.images[1..] {
+            let _ = write!(
+                out,
+                "\nThen: {} at {:#010x} ({})",
+                path.display(),
+                addr,
+                pretty_bytes(*len, Some(2))
+            );
+        }
+        if self.settings.protocol.allows(Cmd::Jump) {
+            let _ = write!(out, "\nJump: {:#010x}", self.settings.jump_addr);
+        }
+        if let Some(config) = &self.config {
+            let _ = write!(out, "\nSettings from: {}", config.path.display());
+        }
+
+        out
+    }
+}
@@ -0,0 +1,315 @@
+/*! Snapshot of a running transfer.
+
+Everything that reports progress (the terminal status bar, the setup dialog,
+the progress window) works from the same numbers, taken from [`State`] by
+[`Progress::current`]. */
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use pretty_bytes_typed::pretty_bytes;
+
+use super::state::{Activity, State};
+
+/// How far back [`RateMeter`] looks.
+pub const RATE_WINDOW: Duration = Duration::from_secs(5);
+/// Width of the bar in [`StatusStyle::Full`].
+const BAR_LENGTH: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub activity: Activity,
+    /// `Load` frames acknowledged, counting only those with nothing older
+    /// still in flight.
+    pub frames_acked: u32,
+    pub total_frames: u32,
+    pub chunk_size: u16,
+    /// Bytes in all images being uploaded.
+    pub total_bytes: u64,
+    /// Frames resent so far.
+    pub retries: u32,
+    pub elapsed: Duration,
+    /// Bytes per second over the last [`RATE_WINDOW`].
+    pub recent_rate: Option<f64>,
+}
+
+impl Progress {
+    /// Progress of the current transfer, or `None` if there isn't one.
+    pub fn current(s: &mut State) -> Option<Self> {
+        if !s.activity.in_transfer() {
+            return None;
+        }
+
+        let loader = s.sfl_loader.as_mut()?;
+        let total_frames = loader.total_frames().ok()?;
+
+        Some(Self {
+            activity: s.activity,
+            frames_acked: s.last_frame_acked.map_or(0, |a| a + 1),
+            total_frames,
+            chunk_size: loader.chunk_size,
+            total_bytes: s.file_size.unwrap_or(0),
+            retries: s.stats.frames_resent,
+            elapsed: s.start_time.map_or(Duration::ZERO, |t| Instant::now() - t),
+            recent_rate: s.rate.rate(),
+        })
+    }
+
+    /// Bytes acknowledged so far. Approximate, since the last frame of each
+    /// image may be short.
+    pub fn bytes_acked(&self) -> u64 {
+        (self.frames_acked as u64 * self.chunk_size as u64).min(self.total_bytes)
+    }
+
+    /// Fraction of `Load` frames acknowledged, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.total_frames == 0 {
+            return 0.0;
+        }
+
+        self.frames_acked as f64 / self.total_frames as f64
+    }
+
+    /// Average bytes per second so far, or `None` before anything has been
+    /// acknowledged.
+    pub fn rate(&self) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        let bytes = self.bytes_acked();
+        (bytes > 0 && secs > 0.0).then(|| bytes as f64 / secs)
+    }
+
+    /// The recent rate if there is one, so that estimates follow changes
+    /// in speed, otherwise the average.
+    pub fn current_rate(&self) -> Option<f64> {
+        self.recent_rate.or_else(|| self.rate())
+    }
+
+    /// Estimated time until the last `Load` frame is acknowledged, at the
+    /// current rate.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total_bytes - self.bytes_acked();
+        self.current_rate()
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+/// Throughput over the last [`RATE_WINDOW`], from samples of how many bytes
+/// had been acknowledged when.
+#[derive(Debug, Clone, Default)]
+pub struct RateMeter {
+    samples: VecDeque<(Duration, u64)>,
+}
+
+impl RateMeter {
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Record that `bytes` had been acknowledged `at` into the transfer.
+    pub fn record(&mut self, at: Duration, bytes: u64) {
+        self.samples.push_back((at, bytes));
+
+        // Keep one sample at or beyond the window, so the rate covers all of
+        // it.
+        while self.samples.len() > 2 && at - self.samples[1].0 >= RATE_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second between the oldest and newest samples.
+    pub fn rate(&self) -> Option<f64> {
+        let (t0, b0) = self.samples.front()?;
+        let (t1, b1) = self.samples.back()?;
+        let secs = (*t1 - *t0).as_secs_f64();
+
+        (secs > 0.0).then(|| b1.saturating_sub(*b0) as f64 / secs)
+    }
+}
+
+/// What the terminal progress line shows.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StatusStyle {
+    /// Bar, percentage, bytes, rate, ETA, chunk size and retries.
+    #[default]
+    Full,
+    /// Percentage, bytes and ETA, for narrow terminals.
+    Compact,
+}
+
+impl StatusStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "full" => Some(StatusStyle::Full),
+            "compact" => Some(StatusStyle::Compact),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusStyle::Full => "full",
+            StatusStyle::Compact => "compact",
+        }
+    }
+}
+
+fn format_eta(eta: Option<Duration>) -> String {
+    match eta {
+        Some(eta) => {
+            let secs = eta.as_secs();
+            format!("{}:{:02}", secs / 60, secs % 60)
+        }
+        None => "--:--".to_string(),
+    }
+}
+
+/// The terminal progress line, without colors.
+pub fn status_line(p: &Progress, style: StatusStyle) -> String {
+    // Round down, so 100% means done.
+    let percent = (p.fraction() * 100.0) as u32;
+    let acked = pretty_bytes(p.bytes_acked(), Some(1));
+    let total = pretty_bytes(p.total_bytes, Some(1));
+    let eta = format_eta(p.eta());
+    let mut line = String::new();
+
+    match style {
+        StatusStyle::Full => {
+            let used = (BAR_LENGTH as f64 * p.fraction()) as usize;
+            line.push('|');
+            line.push_str(&"=".repeat(used));
+            // Arrow goes away once 100% loaded!
+            if used < BAR_LENGTH {
+                line.push('>');
+                line.push_str(&" ".repeat(BAR_LENGTH - used - 1));
+            }
+            line.push('|');
+
+            let rate = p.current_rate().map_or("-".to_string(), |r| {
+                format!("{}/s", pretty_bytes(r as u64, Some(1)))
+            });
+            let _ = write!(
+                line,
+                " {:>3}% {} / {}, {}, ETA {}, chunk {}, {} retries",
+                percent, acked, total, rate, eta, p.chunk_size, p.retries
+            );
+        }
+        StatusStyle::Compact => {
+            let _ = write!(line, "{:>3}% {}/{} ETA {}", percent, acked, total, eta);
+            if p.retries > 0 {
+                let _ = write!(line, " R{}", p.retries);
+            }
+        }
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(frames_acked: u32, elapsed_secs: u64) -> Progress {
+        Progress {
+            activity: Activity::WaitResp,
+            frames_acked,
+            total_frames: 100,
+            chunk_size: 100,
+            total_bytes: 10_000,
+            retries: 0,
+            elapsed: Duration::from_secs(elapsed_secs),
+            recent_rate: None,
+        }
+    }
+
+    #[test]
+    fn full_line_at_start() {
+        let line = status_line(&progress(0, 0), StatusStyle::Full);
+        assert_eq!(
+            line,
+            format!(
+                "|>{}|   0% 0 B / 10 KB, -, ETA --:--, chunk 100, 0 retries",
+                " ".repeat(39)
+            )
+        );
+    }
+
+    #[test]
+    fn full_line_halfway() {
+        let line = status_line(&progress(50, 10), StatusStyle::Full);
+        assert_eq!(
+            line,
+            format!(
+                "|{}>{}|  50% 5 KB / 10 KB, 500 B/s, ETA 0:10, chunk 100, 0 retries",
+                "=".repeat(20),
+                " ".repeat(19)
+            )
+        );
+    }
+
+    #[test]
+    fn full_line_done() {
+        let line = status_line(&progress(100, 20), StatusStyle::Full);
+        assert!(line.starts_with(&format!("|{}| 100% 10 KB / 10 KB", "=".repeat(40))));
+        assert!(line.contains("ETA 0:00"));
+    }
+
+    #[test]
+    fn compact_line() {
+        let mut p = progress(25, 5);
+        assert_eq!(
+            status_line(&p, StatusStyle::Compact),
+            " 25% 2.5 KB/10 KB ETA 0:15"
+        );
+
+        p.retries = 3;
+        assert_eq!(
+            status_line(&p, StatusStyle::Compact),
+            " 25% 2.5 KB/10 KB ETA 0:15 R3"
+        );
+    }
+
+    #[test]
+    fn eta_follows_recent_rate() {
+        let mut p = progress(50, 10);
+        p.recent_rate = Some(1000.0);
+        assert_eq!(p.eta(), Some(Duration::from_secs(5)));
+
+        // Stalled: no estimate rather than forever.
+        p.recent_rate = Some(0.0);
+        assert_eq!(p.eta(), None);
+    }
+
+    #[test]
+    fn rate_meter_window() {
+        let mut meter = RateMeter::default();
+        assert_eq!(meter.rate(), None);
+
+        meter.record(Duration::from_secs(0), 0);
+        assert_eq!(meter.rate(), None);
+
+        // 100 B/s for 10 s, then 1000 B/s.
+        for t in 1..=10 {
+            meter.record(Duration::from_secs(t), t * 100);
+        }
+        assert_eq!(meter.rate(), Some(100.0));
+
+        for t in 11..=20 {
+            meter.record(Duration::from_secs(t), 1000 + (t - 10) * 1000);
+        }
+        assert_eq!(meter.rate(), Some(1000.0));
+    }
+
+    #[test]
+    fn style_names() {
+        for style in [StatusStyle::Full, StatusStyle::Compact] {
+            assert_eq!(StatusStyle::from_name(style.name()), Some(style));
+        }
+        assert_eq!(
+            StatusStyle::from_name("Compact"),
+            Some(StatusStyle::Compact)
+        );
+        assert_eq!(StatusStyle::from_name("wide"), None);
+    }
+}
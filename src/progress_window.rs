@@ -0,0 +1,201 @@
+/*! Modeless window showing the progress of an upload.
+
+Opened when an upload starts if "Show progress window" is checked. It polls
+[`Progress`] on a timer, mirrors it on Tera Term's taskbar button, and closes
+itself once the upload is over. */
+
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use log::*;
+use pretty_bytes_typed::pretty_bytes;
+
+use super::io;
+use super::progress::Progress;
+use super::state::{Activity, State, OUR_HINST, TTX_LITEX_STATE};
+use super::ui::{IDC_LITEX_PROGRESS_BAR, IDC_LITEX_PROGRESS_TEXT, IDD_LITEX_PROGRESS};
+
+use widestring::U16CString;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::*;
+use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+use windows::Win32::UI::Controls::{PBM_SETPOS, PBM_SETRANGE32};
+use windows::Win32::UI::Shell::{
+    ITaskbarList3, TaskbarList, TBPF_INDETERMINATE, TBPF_NOPROGRESS, TBPF_NORMAL,
+};
+use windows::Win32::UI::WindowsAndMessaging::*;
+
+const REFRESH_TIMER_ID: usize = 1;
+const REFRESH_INTERVAL_MS: u32 = 250;
+/// Steps in the progress bar.
+const BAR_RANGE: u32 = 1000;
+
+thread_local! {
+    static WINDOW: Cell<Option<HWND>> = const { Cell::new(None) };
+    static TASKBAR: RefCell<Option<ITaskbarList3>> = const { RefCell::new(None) };
+}
+
+/// Open the progress window, unless it's already open.
+pub fn open(s: &State) {
+    if s.cv.is_null() || WINDOW.get().is_some() {
+        return;
+    }
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+    let owner = unsafe { (*s.cv).HWin };
+    let res = unsafe {
+        CreateDialogParamW(
+            Some(OUR_HINST.get()),
+            PCWSTR(IDD_LITEX_PROGRESS as *const u16),
+            Some(owner),
+            Some(Some(progress_dialog)),
+            LPARAM(0),
+        )
+    };
+
+    match res {
+        Ok(window) => WINDOW.set(Some(window)),
+        Err(e) => error!(target: "progress_window", "Could not open progress window: {}", e),
+    }
+}
+
+fn taskbar() -> Option<ITaskbarList3> {
+    let taskbar: ITaskbarList3 =
+        unsafe { CoCreateInstance(&TaskbarList, None, CLSCTX_INPROC_SERVER) }
+            .inspect_err(|e| warn!(target: "progress_window", "No taskbar progress: {}", e))
+            .ok()?;
+    unsafe { taskbar.HrInit() }.ok()?;
+
+    Some(taskbar)
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn describe(p: &Progress) -> String {
+    match p.activity {
+        Activity::Calibrate => {
+            return format!("Calibrating chunk size ({} bytes)...", p.chunk_size);
+        }
+        Activity::WaitFinalResp => {
+            return "Upload complete, waiting for the receiver to jump.".to_string();
+        }
+        _ => {}
+    }
+
+    let rate = p.current_rate().map_or("-".to_string(), |r| {
+        format!("{}/s", pretty_bytes(r as u64, Some(1)))
+    });
+    let eta = p.eta().map_or("-".to_string(), format_duration);
+
+    format!(
+        "{:.0}%: {} of {}\r\nFrames: {} / {}, {} retries\r\n{}, {} elapsed, {} left",
+        p.fraction() * 100.0,
+        pretty_bytes(p.bytes_acked(), Some(1)),
+        pretty_bytes(p.total_bytes, Some(1)),
+        p.frames_acked,
+        p.total_frames,
+        p.retries,
+        rate,
+        format_duration(p.elapsed),
+        eta
+    )
+}
+
+/// Show the current progress, or close the window if the upload is over.
+fn refresh(dialog: HWND) {
+    let Some(progress) = TTX_LITEX_STATE.with_borrow_mut(Progress::current) else {
+        let _ = unsafe { DestroyWindow(dialog) };
+        return;
+    };
+
+    let pos = (progress.fraction() * BAR_RANGE as f64) as usize;
+    let text = U16CString::from_str_truncate(describe(&progress));
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_PROGRESS_BAR as i32,
+            PBM_SETPOS,
+            WPARAM(pos),
+            LPARAM(0),
+        );
+        let _ = SetDlgItemTextW(
+            dialog,
+            IDC_LITEX_PROGRESS_TEXT as i32,
+            PCWSTR(text.as_ptr()),
+        );
+    }
+
+    TASKBAR.with_borrow(|taskbar| {
+        let Some(taskbar) = taskbar else {
+            return;
+        };
+        let Ok(owner) = (unsafe { GetWindow(dialog, GW_OWNER) }) else {
+            return;
+        };
+
+        let _ = unsafe {
+            if progress.activity == Activity::Calibrate {
+                taskbar.SetProgressState(owner, TBPF_INDETERMINATE)
+            } else {
+                taskbar
+                    .SetProgressState(owner, TBPF_NORMAL)
+                    .and_then(|_| taskbar.SetProgressValue(owner, pos as u64, BAR_RANGE as u64))
+            }
+        };
+    });
+}
+
+unsafe extern "system" fn progress_dialog(
+    dialog: HWND,
+    msg: u32,
+    param_1: WPARAM,
+    _param_2: LPARAM,
+) -> isize {
+    match msg {
+        // Sent from inside `open`, while the state is still borrowed, so
+        // the first refresh waits for the timer.
+        WM_INITDIALOG => {
+            let _ = SendDlgItemMessageW(
+                dialog,
+                IDC_LITEX_PROGRESS_BAR as i32,
+                PBM_SETRANGE32,
+                WPARAM(0),
+                LPARAM(BAR_RANGE as isize),
+            );
+            TASKBAR.set(taskbar());
+            SetTimer(Some(dialog), REFRESH_TIMER_ID, REFRESH_INTERVAL_MS, None);
+
+            return true.into();
+        }
+        WM_TIMER if param_1.0 == REFRESH_TIMER_ID => {
+            refresh(dialog);
+            return true.into();
+        }
+        WM_COMMAND if (param_1.0 & 0xFFFF) as i32 == IDCANCEL.0 => {
+            trace!(target: "progress_window", "Cancel");
+            TTX_LITEX_STATE.with_borrow_mut(|s| {
+                if s.activity.in_transfer() {
+                    let msg = io::abort_transfer(s, "cancelled from the progress window.");
+                    s.notices.push_back(msg.into());
+                }
+            });
+            let _ = DestroyWindow(dialog);
+            return true.into();
+        }
+        WM_DESTROY => {
+            let _ = KillTimer(Some(dialog), REFRESH_TIMER_ID);
+            if let Some(taskbar) = TASKBAR.take() {
+                if let Ok(owner) = GetWindow(dialog, GW_OWNER) {
+                    let _ = taskbar.SetProgressState(owner, TBPF_NOPROGRESS);
+                }
+            }
+            WINDOW.set(None);
+        }
+        _ => {}
+    }
+
+    false.into()
+}
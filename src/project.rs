@@ -0,0 +1,485 @@
+/*! Project-local configuration.
+
+A `ttlitex.toml` file next to the firmware, or in any directory above it,
+records the upload settings for that firmware, so they can be checked into
+the firmware's repository instead of living only in each user's
+`TERATERM.INI`:
+
+```toml
+load-address = 0x40000000
+jump-address = "0x40000000"
+
+[[images]]
+file = "rootfs.cpio"
+address = "0x41000000"
+
+[transfer]
+protocol = "LiteX"
+``` */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use super::expr::{self, Symbols};
+use super::sfl::profile::{self, MAX_PAYLOAD, MIN_PAYLOAD};
+use super::sfl::{self, Cmd, Profile};
+use super::state::State;
+use super::Error;
+
+pub const FILE_NAME: &str = "ttlitex.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    #[serde(deserialize_with = "address")]
+    pub load_address: Option<u32>,
+    #[serde(deserialize_with = "address")]
+    pub jump_address: Option<u32>,
+    /// Extra images, uploaded in order after the chosen file.
+    pub images: Vec<Image>,
+    pub transfer: Transfer,
+
+    /// Where this config was read from. Relative image paths are resolved
+    /// against its directory.
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Image {
+    pub file: PathBuf,
+    #[serde(deserialize_with = "required_address")]
+    pub address: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Transfer {
+    pub protocol: Option<String>,
+    pub magic: Option<String>,
+    pub response: Option<String>,
+    pub max_payload: Option<u8>,
+    pub jump: Option<bool>,
+}
+
+/// Addresses may be written as TOML integers, or as strings in any format
+/// the dialog accepts (e.g. `"0x4000_0000 + 1M"`). There are no symbols to
+/// use, though.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAddress {
+    Int(u32),
+    Str(String),
+}
+
+fn required_address<'de, D>(d: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match RawAddress::deserialize(d)? {
+        RawAddress::Int(a) => Ok(a),
+        RawAddress::Str(s) => {
+            expr::parse_address(&s, &Symbols::default()).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+fn address<'de, D>(d: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    required_address(d).map(Some)
+}
+
+impl ProjectConfig {
+    /// Look for a config file in the directory containing `firmware`, then
+    /// in each of its parents.
+    pub fn find(firmware: &Path) -> Option<PathBuf> {
+        let firmware = firmware.canonicalize().ok()?;
+
+        firmware
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|cfg| cfg.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bad_config = |msg: String| Error::BadConfig {
+            path: path.to_path_buf(),
+            msg,
+        };
+
+        let text = fs::read_to_string(path).map_err(|e| bad_config(e.to_string()))?;
+        let mut config: Self = toml::from_str(&text).map_err(|e| bad_config(e.to_string()))?;
+        config.path = path.to_path_buf();
+
+        // Check the transfer settings now, rather than halfway through an
+        // upload.
+        config.protocol(&Profile::default()).map_err(bad_config)?;
+
+        // Images that aren't built yet are caught when uploading.
+        for (image, addr) in config.image_paths() {
+            let Ok(meta) = image.metadata() else {
+                continue;
+            };
+            if !sfl::image_fits(addr, meta.len()) {
+                return Err(bad_config(format!(
+                    "{} ({} bytes) at {:#010x} would run past the end of memory",
+                    image.display(),
+                    meta.len(),
+                    addr
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Find and load the config for `firmware`, if there is one.
+    pub fn discover(firmware: &Path) -> Result<Option<Self>, Error> {
+        Self::find(firmware).map(|p| Self::load(&p)).transpose()
+    }
+
+    /// Extra images, with paths resolved relative to the config file.
+    pub fn image_paths(&self) -> impl Iterator<Item = (PathBuf, u32)> + '_ {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        self.images
+            .iter()
+            .map(move |img| (dir.join(&img.file), img.address))
+    }
+
+    /// `base` with this config's transfer settings applied on top.
+    pub fn protocol(&self, base: &Profile) -> Result<Profile, String> {
+        let t = &self.transfer;
+
+        let mut protocol = match &t.protocol {
+            Some(name) => {
+                Profile::by_name(name).ok_or_else(|| format!("unknown protocol {}", name))?
+            }
+            None => base.clone(),
+        };
+
+        let customized = t.magic.is_some()
+            || t.response.is_some()
+            || t.max_payload.is_some()
+            || t.jump.is_some();
+        if !customized {
+            return Ok(protocol);
+        }
+
+        // Anything beyond picking a profile needs the editable one.
+        if !protocol.is_custom() {
+            protocol = Profile {
+                name: Profile::CUSTOM.to_string(),
+                ..protocol
+            };
+        }
+
+        if let Some(magic) = &t.magic {
+            protocol.magic = profile::unescape(magic)
+                .filter(|m| !m.is_empty())
+                .ok_or("magic string is empty or has a bad escape")?;
+        }
+        if let Some(response) = &t.response {
+            protocol.response =
+                profile::unescape(response).ok_or("response string has a bad escape")?;
+        }
+        if let Some(max_payload) = t.max_payload {
+            if !(MIN_PAYLOAD..=MAX_PAYLOAD).contains(&max_payload) {
+                return Err("max-payload must be between 5 and 255".to_string());
+            }
+            protocol.max_payload = max_payload;
+        }
+        match t.jump {
+            Some(true) if !protocol.allows(Cmd::Jump) => protocol.commands.push(Cmd::Jump),
+            Some(false) => protocol.commands.retain(|c| *c != Cmd::Jump),
+            _ => {}
+        }
+
+        Ok(protocol)
+    }
+}
+
+/// Settings for one upload: the user's, with a project config's on top.
+/// Kept apart from the user's settings, so that a config never ends up in
+/// the dialog or in `TERATERM.INI`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadSettings {
+    pub addr: u32,
+    /// Where the Jump frame sends the receiver.
+    pub jump_addr: u32,
+    pub protocol: Profile,
+}
+
+impl UploadSettings {
+    /// The user's settings in `s`, with `config`'s on top. Settings the
+    /// config doesn't mention are the user's.
+    pub fn new(s: &State, config: Option<&ProjectConfig>) -> Result<Self, Error> {
+        let Some(config) = config else {
+            return Ok(Self {
+                addr: s.addr,
+                jump_addr: s.jump_addr.unwrap_or(s.addr),
+                protocol: s.protocol.clone(),
+            });
+        };
+
+        let addr = config.load_address.unwrap_or(s.addr);
+        let protocol = config
+            .protocol(&s.protocol)
+            .map_err(|msg| Error::BadConfig {
+                path: config.path.clone(),
+                msg,
+            })?;
+
+        Ok(Self {
+            addr,
+            jump_addr: config.jump_address.or(s.jump_addr).unwrap_or(addr),
+            protocol,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process, ptr};
+
+    fn parse(text: &str) -> Result<ProjectConfig, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn transfer(text: &str) -> ProjectConfig {
+        parse(&format!("[transfer]\n{}", text)).unwrap()
+    }
+
+    /// A scratch directory tree, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("ttxlitex-{}-{}", name, process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        let config = parse(
+            r#"
+            load-address = 0x40000000
+            jump-address = "0x4000_0000 + 1M"
+
+            [[images]]
+            file = "rootfs.cpio"
+            address = "0x41000000"
+
+            [[images]]
+            file = "dtb"
+            address = 1090519040
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.load_address, Some(0x4000_0000));
+        assert_eq!(config.jump_address, Some(0x4010_0000));
+        assert_eq!(config.images.len(), 2);
+        assert_eq!(config.images[0].address, 0x4100_0000);
+        assert_eq!(config.images[1].address, 0x4100_0000);
+    }
+
+    #[test]
+    fn empty_config() {
+        let config = parse("").unwrap();
+        assert_eq!(config.load_address, None);
+        assert_eq!(config.jump_address, None);
+        assert!(config.images.is_empty());
+        assert_eq!(config.protocol(&Profile::litex()), Ok(Profile::litex()));
+    }
+
+    #[test]
+    fn bad_configs() {
+        for text in [
+            "load-adress = 0x40000000",
+            "load-address = \"main_ram\"",
+            "load-address = -1",
+            "load-address = 0x100000000",
+            "jump-address = true",
+            "[[images]]\nfile = \"rootfs.cpio\"",
+            "[[images]]\nfile = \"rootfs.cpio\"\naddress = 0\nsize = 4",
+            "[transfer]\nmagic-string = \"x\"",
+            "[transfer]\nmax-payload = 300",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn protocol_from_transfer() {
+        let custom = Profile::custom();
+        assert_eq!(
+            transfer("protocol = \"LiteX\"").protocol(&custom),
+            Ok(Profile::litex())
+        );
+
+        let protocol = transfer(
+            r#"
+            magic = "flterm\r\n"
+            response = "\\x00ok"
+            max-payload = 64
+            jump = false
+            "#,
+        )
+        .protocol(&Profile::litex())
+        .unwrap();
+        assert!(protocol.is_custom());
+        assert_eq!(protocol.magic, b"flterm\r\n");
+        assert_eq!(protocol.response, b"\x00ok");
+        assert_eq!(protocol.max_payload, 64);
+        assert!(!protocol.allows(Cmd::Jump));
+
+        // Settings it doesn't mention come from the base profile.
+        let base = Profile {
+            max_payload: 32,
+            commands: vec![Cmd::Load],
+            ..Profile::custom()
+        };
+        let protocol = transfer("jump = true").protocol(&base).unwrap();
+        assert_eq!(protocol.max_payload, 32);
+        assert!(protocol.allows(Cmd::Jump));
+    }
+
+    #[test]
+    fn bad_transfer() {
+        for text in [
+            "protocol = \"flterm\"",
+            "magic = \"\"",
+            "magic = \"\\\\xZZ\"",
+            "response = \"\\\\x4\"",
+            "max-payload = 4",
+        ] {
+            assert!(
+                transfer(text).protocol(&Profile::litex()).is_err(),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn relative_image_paths() {
+        let mut config = parse(
+            r#"
+            [[images]]
+            file = "build/rootfs.cpio"
+            address = 0
+            "#,
+        )
+        .unwrap();
+        config.path = Path::new("project").join(FILE_NAME);
+
+        let paths: Vec<_> = config.image_paths().collect();
+        assert_eq!(paths, [(Path::new("project").join("build/rootfs.cpio"), 0)]);
+    }
+
+    #[test]
+    fn find_walks_up() {
+        let dir = TempDir::new("project-find");
+        let firmware = dir.write("sw/build/firmware.bin", [0; 16]);
+        assert_eq!(ProjectConfig::find(&firmware), None);
+
+        let outer = dir.write(FILE_NAME, "");
+        let root = dir.0.canonicalize().unwrap();
+        assert_eq!(ProjectConfig::find(&firmware), Some(root.join(FILE_NAME)));
+
+        // The nearest one wins.
+        dir.write("sw/ttlitex.toml", "");
+        assert_eq!(
+            ProjectConfig::find(&firmware),
+            Some(root.join("sw").join(FILE_NAME))
+        );
+        assert!(outer.is_file());
+    }
+
+    #[test]
+    fn load_checks_settings() {
+        let dir = TempDir::new("project-load");
+        dir.write("big.bin", [0; 32]);
+        let path = dir.write(
+            FILE_NAME,
+            "[[images]]\nfile = \"missing.bin\"\naddress = 0xfffffff0\n",
+        );
+        let config = ProjectConfig::load(&path).unwrap();
+        assert_eq!(config.path, path);
+
+        for text in [
+            "[transfer]\nmax-payload = 4\n",
+            "[[images]]\nfile = \"big.bin\"\naddress = 0xfffffff0\n",
+            "load-address =",
+        ] {
+            fs::write(&path, text).unwrap();
+            assert!(
+                matches!(ProjectConfig::load(&path), Err(Error::BadConfig { .. })),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn upload_settings() {
+        let mut s = State::new(ptr::null_mut(), ptr::null_mut());
+        s.addr = 0x4000_0000;
+
+        let upload = UploadSettings::new(&s, None).unwrap();
+        assert_eq!(upload.addr, 0x4000_0000);
+        assert_eq!(upload.jump_addr, 0x4000_0000);
+        assert_eq!(upload.protocol, s.protocol);
+
+        // The config's load address is also where to jump, unless the user
+        // says otherwise.
+        let config = ProjectConfig {
+            load_address: Some(0x5000_0000),
+            ..ProjectConfig::default()
+        };
+        let upload = UploadSettings::new(&s, Some(&config)).unwrap();
+        assert_eq!(upload.addr, 0x5000_0000);
+        assert_eq!(upload.jump_addr, 0x5000_0000);
+        s.jump_addr = Some(0x4000_1000);
+        let upload = UploadSettings::new(&s, Some(&config)).unwrap();
+        assert_eq!(upload.jump_addr, 0x4000_1000);
+
+        let config = ProjectConfig {
+            transfer: Transfer {
+                max_payload: Some(4),
+                ..Transfer::default()
+            },
+            ..ProjectConfig::default()
+        };
+        assert!(matches!(
+            UploadSettings::new(&s, Some(&config)),
+            Err(Error::BadConfig { .. })
+        ));
+
+        // None of it touches the user's settings.
+        assert_eq!(s.addr, 0x4000_0000);
+        assert_eq!(s.protocol, Profile::litex());
+    }
+}
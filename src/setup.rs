@@ -0,0 +1,1083 @@
+/*! Setup hooks, for saving and restoring settings in TERATERM.INI, and for
+parsing our command-line options.
+
+Tera Term reads the INI file once at startup (and again on "Restore setup"),
+and writes it on "Save setup". We piggyback on both by wrapping whatever
+ReadIniFile/WriteIniFile was installed before us, and keep our own settings
+in a `[TTXLiteX]` section.
+
+Command-line parameters are handed to every plugin's ParseParam in turn
+after the INI file is read. We blank out the `/litex-*` options we understand
+before passing the command line on, so that Tera Term never sees them.
+
+`TTX_LITEX_*` environment variables are read once by TTXInit, but are
+reapplied every time the INI file is read so that they always win. */
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::slice;
+
+use log::*;
+use parse_int::parse;
+use widestring::U16CString;
+use windows::core::PCWSTR;
+use windows::Win32::System::WindowsProgramming::{
+    GetPrivateProfileStringW, WritePrivateProfileStringW,
+};
+
+use super::board::Board;
+use super::expr::{self, Symbols};
+use super::history::History;
+use super::io;
+use super::options::{MessageLevel, TransferOptions};
+use super::sfl::profile::{self, MAX_PAYLOAD, MIN_PAYLOAD};
+use super::sfl::{Cmd, Profile, SflLoader};
+use super::state::{Activity, State, TTX_LITEX_STATE};
+use super::tt;
+use super::Error;
+
+pub const SECTION: &str = "TTXLiteX";
+pub const HISTORY_SECTION: &str = "TTXLiteX History";
+
+/// A section of an INI file.
+pub struct Ini {
+    fname: PCWSTR,
+    section: U16CString,
+}
+
+impl Ini {
+    /// # Safety
+    /// `fname` must be a valid, NUL-terminated wide string that outlives
+    /// the returned value.
+    pub unsafe fn new(fname: *const tt::wchar_t, section: &str) -> Self {
+        Self {
+            fname: PCWSTR(fname),
+            section: U16CString::from_str_truncate(section),
+        }
+    }
+
+    /// Get the value of `key`. Missing and empty keys are both `None`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let key = U16CString::from_str_truncate(key);
+        let mut buf: Vec<u16> = vec![0; 256];
+
+        loop {
+            let len = unsafe {
+                GetPrivateProfileStringW(
+                    PCWSTR(self.section.as_ptr()),
+                    PCWSTR(key.as_ptr()),
+                    PCWSTR::null(),
+                    Some(&mut buf),
+                    self.fname,
+                )
+            } as usize;
+
+            // A full buffer means the value was truncated.
+            if len + 1 >= buf.len() && buf.len() < 32768 {
+                buf.resize(buf.len() * 2, 0);
+                continue;
+            }
+
+            buf.truncate(len);
+            break;
+        }
+
+        if buf.is_empty() {
+            None
+        } else {
+            Some(String::from_utf16_lossy(&buf))
+        }
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)
+            .and_then(|v| match v.to_ascii_lowercase().as_str() {
+                "on" | "yes" | "true" | "1" => Some(true),
+                "off" | "no" | "false" | "0" => Some(false),
+                _ => {
+                    warn!(target: "Ini", "Ignoring non-boolean value for {}: {}", key, v);
+                    None
+                }
+            })
+    }
+
+    /// Get a decimal or hex integer.
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        self.get(key).and_then(|v| {
+            parse::<u32>(&v)
+                .inspect_err(|_| warn!(target: "Ini", "Ignoring bad value for {}: {}", key, v))
+                .ok()
+        })
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<(), Error> {
+        let key = U16CString::from_str_truncate(key);
+        let value = U16CString::from_str_truncate(value);
+
+        unsafe {
+            WritePrivateProfileStringW(
+                PCWSTR(self.section.as_ptr()),
+                PCWSTR(key.as_ptr()),
+                PCWSTR(value.as_ptr()),
+                self.fname,
+            )
+        }
+        .map_err(Error::WinError)
+    }
+
+    pub fn set_bool(&self, key: &str, value: bool) -> Result<(), Error> {
+        self.set(key, if value { "on" } else { "off" })
+    }
+
+    /// Another section of the same INI file.
+    pub fn sibling(&self, section: &str) -> Self {
+        Self {
+            fname: self.fname,
+            section: U16CString::from_str_truncate(section),
+        }
+    }
+
+    /// Remove this section, and every key in it.
+    pub fn delete(&self) -> Result<(), Error> {
+        unsafe {
+            WritePrivateProfileStringW(
+                PCWSTR(self.section.as_ptr()),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                self.fname,
+            )
+        }
+        .map_err(Error::WinError)
+    }
+}
+
+/// Restore plugin settings from the `[TTXLiteX]` section, protocol
+/// profiles from the `[TTXLiteX Protocol N]` sections, and board profiles
+/// from the `[TTXLiteX Board N]` sections.
+pub fn load_settings(ini: &Ini, s: &mut State) {
+    load_protocols(ini, s);
+    let mut current = Board::from_state("", s);
+    load_board(ini, &mut current, &s.protocols);
+    current.apply(s);
+
+    s.board = ini.get("Board");
+    load_boards(ini, s);
+    s.history = History::load(&ini.sibling(HISTORY_SECTION));
+    s.options = TransferOptions::load(ini);
+
+    // Don't yank the FSM out from under a transfer if the user asks to
+    // restore their setup mid-upload.
+    if s.activity.in_transfer() {
+        return;
+    }
+
+    if ini.get_bool("Active").unwrap_or(false) && s.filename.is_some() {
+        s.arm();
+        info!(target: "load_settings", "Plugin now actively searching for magic string.");
+    } else {
+        s.disarm();
+    }
+}
+
+fn load_boards(ini: &Ini, s: &mut State) {
+    s.boards.clear();
+    s.port_boards.clear();
+
+    for i in 1..=ini.get_u32("BoardCount").unwrap_or(0) {
+        let section = ini.sibling(&format!("{} Board {}", SECTION, i));
+        let Some(name) = section.get("Name") else {
+            warn!(target: "load_boards", "Board {} has no name, skipping", i);
+            continue;
+        };
+
+        let mut board = Board::new(&name);
+        load_board(&section, &mut board, &s.protocols);
+
+        for port in section.get("Ports").unwrap_or_default().split(',') {
+            if let Ok(port) = port.trim().parse::<u16>() {
+                s.port_boards.insert(port, name.clone());
+            }
+        }
+
+        s.boards.push(board);
+    }
+}
+
+fn load_protocols(ini: &Ini, s: &mut State) {
+    s.protocols.clear();
+
+    for i in 1..=ini.get_u32("ProtocolCount").unwrap_or(0) {
+        let section = ini.sibling(&format!("{} Protocol {}", SECTION, i));
+        let Some(name) = section.get("Name") else {
+            warn!(target: "load_protocols", "Protocol {} has no name, skipping", i);
+            continue;
+        };
+        if Profile::by_name(&name).is_some() || s.protocols.iter().any(|p| p.name == name) {
+            warn!(target: "load_protocols", "Skipping duplicate protocol {}", name);
+            continue;
+        }
+
+        let mut protocol = Profile {
+            name,
+            ..Profile::custom()
+        };
+        load_custom_protocol(&section, &mut protocol);
+        s.protocols.push(protocol);
+    }
+}
+
+fn load_board(ini: &Ini, b: &mut Board, protocols: &[Profile]) {
+    if let Some(f) = ini.get("File") {
+        b.filename = Some(PathBuf::from(f));
+    }
+
+    if let Some(addr) = ini.get_u32("BootAddress") {
+        b.addr = addr;
+    }
+
+    // Blank means "jump to the boot address".
+    b.jump_addr = ini.get_u32("JumpAddress");
+
+    b.csr_json = ini
+        .get("CsrJson")
+        .filter(|f| !f.is_empty())
+        .map(PathBuf::from);
+    b.region = ini.get("Region").filter(|r| !r.is_empty());
+    b.symbols = ini
+        .get("Symbols")
+        .map(|s| parse_symbols(&s))
+        .unwrap_or_default();
+
+    if let Some(name) = ini.get("Protocol") {
+        match Profile::find(&name, protocols) {
+            Some(mut p) => {
+                if p.is_custom() {
+                    load_custom_protocol(ini, &mut p);
+                }
+
+                b.protocol = p;
+            }
+            None => warn!(target: "load_board", "Unknown protocol profile: {}", name),
+        }
+    }
+}
+
+/// A board's symbol table, written as `name=address` pairs separated by
+/// commas.
+fn parse_symbols(table: &str) -> BTreeMap<String, u32> {
+    let mut symbols = BTreeMap::new();
+
+    for entry in table.split(',').filter(|e| !e.trim().is_empty()) {
+        let parsed = entry
+            .split_once('=')
+            .map(|(name, value)| (name.trim(), value))
+            .filter(|(name, _)| !name.is_empty())
+            .and_then(|(name, value)| Some((name, parse_addr(value.trim()).ok()?)));
+
+        match parsed {
+            Some((name, value)) => {
+                symbols.insert(name.to_string(), value);
+            }
+            None => warn!(target: "load_board", "Ignoring bad symbol: {}", entry.trim()),
+        }
+    }
+
+    symbols
+}
+
+fn load_custom_protocol(ini: &Ini, p: &mut Profile) {
+    if let Some(magic) = ini
+        .get("Magic")
+        .and_then(|m| profile::unescape(&m))
+        .filter(|m| !m.is_empty())
+    {
+        p.magic = magic;
+    }
+
+    if let Some(response) = ini.get("Response").and_then(|r| profile::unescape(&r)) {
+        p.response = response;
+    }
+
+    if let Some(max_payload) = ini
+        .get_u32("MaxPayload")
+        .and_then(|m| u8::try_from(m).ok())
+        .filter(|m| (MIN_PAYLOAD..=MAX_PAYLOAD).contains(m))
+    {
+        p.max_payload = max_payload;
+    }
+
+    if ini.get_bool("Jump") == Some(false) {
+        p.commands.retain(|c| *c != Cmd::Jump);
+    }
+}
+
+/// Save plugin settings to the `[TTXLiteX]` section, protocol profiles to
+/// the `[TTXLiteX Protocol N]` sections, and board profiles to the
+/// `[TTXLiteX Board N]` sections.
+pub fn save_settings(ini: &Ini, s: &State) -> Result<(), Error> {
+    save_board(ini, &Board::from_state("", s))?;
+    s.options.save(ini)?;
+    ini.set_bool("Active", s.activity != Activity::Inactive)?;
+    ini.set("Board", s.board.as_deref().unwrap_or_default())?;
+
+    // Boards may have been deleted since the last save.
+    for i in 1..=ini.get_u32("BoardCount").unwrap_or(0) {
+        ini.sibling(&format!("{} Board {}", SECTION, i)).delete()?;
+    }
+
+    for (i, board) in s.boards.iter().enumerate() {
+        let section = ini.sibling(&format!("{} Board {}", SECTION, i + 1));
+        let ports = s
+            .port_boards
+            .iter()
+            .filter(|(_, name)| **name == board.name)
+            .map(|(port, _)| port.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        section.set("Name", &board.name)?;
+        section.set("Ports", &ports)?;
+        save_board(&section, board)?;
+    }
+    ini.set("BoardCount", &s.boards.len().to_string())?;
+
+    for i in 1..=ini.get_u32("ProtocolCount").unwrap_or(0) {
+        ini.sibling(&format!("{} Protocol {}", SECTION, i))
+            .delete()?;
+    }
+    for (i, protocol) in s.protocols.iter().enumerate() {
+        let section = ini.sibling(&format!("{} Protocol {}", SECTION, i + 1));
+        section.set("Name", &protocol.name)?;
+        save_custom_protocol(&section, protocol)?;
+    }
+    ini.set("ProtocolCount", &s.protocols.len().to_string())?;
+    s.history.save(&ini.sibling(HISTORY_SECTION))?;
+
+    Ok(())
+}
+
+/// Write just the recent files and addresses to the INI file Tera Term was
+/// started with.
+pub fn save_history(s: &State) -> Result<(), Error> {
+    if s.ts.is_null() {
+        return Ok(());
+    }
+
+    // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit, and
+    // keeps SetupFNameW alive for as long as the TTSet.
+    let fname = unsafe { (*s.ts).SetupFNameW };
+    if fname.is_null() {
+        return Ok(());
+    }
+
+    let ini = unsafe { Ini::new(fname, HISTORY_SECTION) };
+    s.history.save(&ini)
+}
+
+fn save_board(ini: &Ini, b: &Board) -> Result<(), Error> {
+    let file = b
+        .filename
+        .as_ref()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    ini.set("File", &file)?;
+    ini.set("BootAddress", &format!("{:#010x}", b.addr))?;
+    ini.set(
+        "JumpAddress",
+        &b.jump_addr
+            .map(|a| format!("{:#010x}", a))
+            .unwrap_or_default(),
+    )?;
+    ini.set(
+        "CsrJson",
+        &b.csr_json
+            .as_ref()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    )?;
+    ini.set("Region", b.region.as_deref().unwrap_or_default())?;
+    ini.set(
+        "Symbols",
+        &b.symbols
+            .iter()
+            .map(|(name, value)| format!("{}={:#010x}", name, value))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )?;
+    ini.set("Protocol", &b.protocol.name)?;
+
+    if b.protocol.is_custom() {
+        save_custom_protocol(ini, &b.protocol)?;
+    }
+
+    Ok(())
+}
+
+fn save_custom_protocol(ini: &Ini, p: &Profile) -> Result<(), Error> {
+    ini.set("Magic", &profile::escape(&p.magic))?;
+    ini.set("Response", &profile::escape(&p.response))?;
+    ini.set("MaxPayload", &p.max_payload.to_string())?;
+    ini.set_bool("Jump", p.allows(Cmd::Jump))
+}
+
+ttx_export! {
+    unsafe fn our_read_ini_file(fname: *const tt::wchar_t, ts: tt::PTTSet) {
+        // Don't hold the borrow while other plugins' hooks run.
+        let orig_read_ini = TTX_LITEX_STATE.with_borrow(|s| s.orig_read_ini);
+        if let Some(read_ini) = orig_read_ini {
+            read_ini(fname, ts);
+        }
+
+        let ini = Ini::new(fname, SECTION);
+        TTX_LITEX_STATE.with_borrow_mut(|s| {
+            load_settings(&ini, s);
+
+            // Environment variables win over the INI file. Parse errors were
+            // already reported by TTXInit.
+            let env_overrides = s.env_overrides.clone();
+            if let Err(e) = apply_overrides(&env_overrides, s) {
+                error!(target: "our_read_ini_file", "Could not apply TTX_LITEX_* variables: {}", e);
+                io::notice(s, MessageLevel::Summary, format_args!("Could not apply TTX_LITEX_* variables: {}", e));
+            }
+            io::update_watch(s);
+        });
+    }
+}
+
+ttx_export! {
+    unsafe fn our_write_ini_file(fname: *const tt::wchar_t, ts: tt::PTTSet) {
+        let orig_write_ini = TTX_LITEX_STATE.with_borrow(|s| s.orig_write_ini);
+        if let Some(write_ini) = orig_write_ini {
+            write_ini(fname, ts);
+        }
+
+        let ini = Ini::new(fname, SECTION);
+        TTX_LITEX_STATE
+            .with_borrow(|s| save_settings(&ini, s))
+            .unwrap_or_else(|e| error!(target: "our_write_ini_file", "Could not save settings: {}", e));
+    }
+}
+
+/// Settings that take precedence over `TERATERM.INI`, from either the
+/// command line or `TTX_LITEX_*` environment variables.
+#[derive(Default, Debug, Clone)]
+pub struct Overrides {
+    pub file: Option<PathBuf>,
+    pub addr: Option<u32>,
+    pub jump_addr: Option<u32>,
+    pub arm: bool,
+}
+
+/// Split a command line into parameters the way Tera Term does: whitespace
+/// separates parameters, except inside double quotes. The quotes themselves
+/// are dropped from the returned text.
+fn split_params(cmdline: &[u16]) -> Vec<(Range<usize>, String)> {
+    let mut params = Vec::new();
+    let mut i = 0;
+
+    while i < cmdline.len() {
+        if cmdline[i] == b' ' as u16 || cmdline[i] == b'\t' as u16 {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut quoted = false;
+        let mut text = Vec::new();
+        while i < cmdline.len() {
+            let c = cmdline[i];
+            if c == b'"' as u16 {
+                quoted = !quoted;
+            } else if !quoted && (c == b' ' as u16 || c == b'\t' as u16) {
+                break;
+            } else {
+                text.push(c);
+            }
+            i += 1;
+        }
+
+        params.push((start..i, String::from_utf16_lossy(&text)));
+    }
+
+    params
+}
+
+/// Addresses given before any `csr.json` is known can be sums of numbers, but
+/// can't use symbols.
+fn parse_addr(addr: &str) -> Result<u32, Error> {
+    expr::parse_address(addr, &Symbols::default())
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "yes" | "true" | "1" => Ok(true),
+        "off" | "no" | "false" | "0" | "" => Ok(false),
+        _ => Err(Error::BadOption {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Pull our options out of `cmdline`, overwriting them with spaces. Anything
+/// starting with `/litex-` is ours: options that fail to parse, are missing
+/// their value or are misspelled are still removed, and reported in
+/// `errors`.
+fn take_cmdline_opts(cmdline: &mut [u16], errors: &mut Vec<Error>) -> Overrides {
+    let mut opts = Overrides::default();
+
+    for (range, param) in split_params(cmdline) {
+        let (name, value) = match param.split_once('=') {
+            Some((n, v)) => (n.to_ascii_lowercase(), Some(v)),
+            None => (param.to_ascii_lowercase(), None),
+        };
+
+        let res = match (name.as_str(), value) {
+            ("/litex-file", Some(f)) => {
+                opts.file = Some(PathBuf::from(f));
+                Ok(())
+            }
+            ("/litex-addr", Some(a)) => parse_addr(a).map(|a| opts.addr = Some(a)),
+            ("/litex-jump", Some(a)) => parse_addr(a).map(|a| opts.jump_addr = Some(a)),
+            ("/litex-arm", None) => {
+                opts.arm = true;
+                Ok(())
+            }
+            (n, v) if n.starts_with("/litex-") => Err(Error::BadOption {
+                name: n.to_string(),
+                value: v.unwrap_or("(missing)").to_string(),
+            }),
+            _ => continue,
+        };
+
+        if let Err(e) = res {
+            errors.push(e);
+        }
+
+        cmdline[range].fill(b' ' as u16);
+    }
+
+    opts
+}
+
+/// Read the `TTX_LITEX_*` environment variables. Variables that fail to
+/// parse are ignored, and reported in `errors`.
+pub fn env_overrides(errors: &mut Vec<Error>) -> Overrides {
+    overrides_from(|var| env::var(var).ok(), errors)
+}
+
+/// [`env_overrides`], looking variables up with `var`.
+fn overrides_from(var: impl Fn(&str) -> Option<String>, errors: &mut Vec<Error>) -> Overrides {
+    let mut opts = Overrides::default();
+
+    if let Some(f) = var("TTX_LITEX_KERNEL") {
+        opts.file = Some(PathBuf::from(f));
+    }
+
+    for (name, field) in [
+        ("TTX_LITEX_ADDRESS", &mut opts.addr),
+        ("TTX_LITEX_JUMP_ADDRESS", &mut opts.jump_addr),
+    ] {
+        if let Some(a) = var(name) {
+            match parse_addr(&a) {
+                Ok(a) => *field = Some(a),
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+
+    if let Some(arm) = var("TTX_LITEX_ARM") {
+        match parse_bool("TTX_LITEX_ARM", &arm) {
+            Ok(arm) => opts.arm = arm,
+            Err(e) => errors.push(e),
+        }
+    }
+
+    opts
+}
+
+/// Log level from `TTX_LITEX_LOG`, if set. `off` silences the logger.
+pub fn env_log_level(errors: &mut Vec<Error>) -> Option<Option<Level>> {
+    log_level_from(env::var("TTX_LITEX_LOG").ok()?, errors)
+}
+
+fn log_level_from(level: String, errors: &mut Vec<Error>) -> Option<Option<Level>> {
+    match level.to_ascii_lowercase().as_str() {
+        "off" => Some(None),
+        l => match l.parse::<Level>() {
+            Ok(l) => Some(Some(l)),
+            Err(_) => {
+                errors.push(Error::BadOption {
+                    name: "TTX_LITEX_LOG".to_string(),
+                    value: level,
+                });
+                None
+            }
+        },
+    }
+}
+
+/// Apply overrides on top of whatever the INI file said.
+pub fn apply_overrides(opts: &Overrides, s: &mut State) -> Result<(), Error> {
+    if let Some(f) = &opts.file {
+        s.filename = Some(f.clone());
+    }
+
+    if let Some(a) = opts.addr {
+        s.addr = a;
+    }
+
+    if let Some(a) = opts.jump_addr {
+        s.jump_addr = Some(a);
+    }
+
+    if opts.arm && !s.activity.in_transfer() {
+        let filename = s
+            .filename
+            .as_ref()
+            .ok_or(Error::FileIoError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "asked to arm without a file to upload",
+            )))?;
+
+        // Catch typos now rather than when the board asks for the file.
+        SflLoader::open(filename, s.addr).map_err(Error::FileIoError)?;
+
+        s.arm();
+        info!(target: "apply_overrides", "Plugin now actively searching for magic string.");
+    }
+
+    Ok(())
+}
+
+ttx_export! {
+    unsafe fn our_parse_param(param: *mut tt::wchar_t, ts: tt::PTTSet, dde_topic: tt::PCHAR) {
+        // SAFETY: Assumes TeraTerm gave us a NUL-terminated, writable string.
+        let mut len = 0;
+        while *param.add(len) != 0 {
+            len += 1;
+        }
+        let mut errors = Vec::new();
+        let opts = take_cmdline_opts(slice::from_raw_parts_mut(param, len), &mut errors);
+        debug!(target: "our_parse_param", "Command line options: {:?}", opts);
+
+        let orig_parse_param = TTX_LITEX_STATE.with_borrow(|s| s.orig_parse_param);
+        if let Some(parse_param) = orig_parse_param {
+            parse_param(param, ts, dde_topic);
+        }
+
+        TTX_LITEX_STATE.with_borrow_mut(|s| {
+            if let Err(e) = apply_overrides(&opts, s) {
+                errors.push(e);
+            }
+
+            for e in errors {
+                error!(target: "our_parse_param", "Bad LiteX command-line option: {}", e);
+                io::notice(s, MessageLevel::Summary, format_args!("Bad command-line option: {}", e));
+            }
+        });
+    }
+}
+
+ttx_export! {
+    pub unsafe fn ttx_get_setup_hooks(hooks: *mut tt::TTXSetupHooks) {
+        TTX_LITEX_STATE.with_borrow_mut(|s| {
+            // SAFETY: Assumes TeraTerm passed us valid pointers.
+            s.orig_read_ini = *(*hooks).ReadIniFile;
+            *(*hooks).ReadIniFile = Some(our_read_ini_file);
+            s.orig_write_ini = *(*hooks).WriteIniFile;
+            *(*hooks).WriteIniFile = Some(our_write_ini_file);
+            s.orig_parse_param = *(*hooks).ParseParam;
+            *(*hooks).ParseParam = Some(our_parse_param);
+
+            trace!(target: "TTXGetSetupHooks", "s.orig_read_ini <= {:?}", s.orig_read_ini);
+            trace!(target: "TTXGetSetupHooks", "s.orig_write_ini <= {:?}", s.orig_write_ini);
+            trace!(target: "TTXGetSetupHooks", "s.orig_parse_param <= {:?}", s.orig_parse_param);
+        });
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::{fs, process, ptr};
+
+    /// A scratch INI file, removed when dropped.
+    pub(crate) struct TempIni {
+        path: PathBuf,
+        wide: U16CString,
+    }
+
+    impl TempIni {
+        pub(crate) fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("ttxlitex-{}-{}.ini", name, process::id()));
+            fs::write(&path, "").unwrap();
+            let wide = U16CString::from_os_str_truncate(path.as_os_str());
+            Self { path, wide }
+        }
+
+        pub(crate) fn section(&self, section: &str) -> Ini {
+            // SAFETY: `wide` outlives every Ini made in a test.
+            unsafe { Ini::new(self.wide.as_ptr(), section) }
+        }
+    }
+
+    impl Drop for TempIni {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn state() -> State {
+        State::new(ptr::null_mut(), ptr::null_mut())
+    }
+
+    fn custom_protocol(name: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            magic: b"flterm\r\n".to_vec(),
+            response: b"\x00ok".to_vec(),
+            commands: vec![Cmd::Abort, Cmd::Load],
+            max_payload: 64,
+        }
+    }
+
+    #[test]
+    fn ini_values() {
+        let file = TempIni::new("values");
+        let ini = file.section(SECTION);
+        ini.set("Name", "demo").unwrap();
+        ini.set("Empty", "").unwrap();
+        ini.set("Long", &"x".repeat(1000)).unwrap();
+
+        assert_eq!(ini.get("Name").as_deref(), Some("demo"));
+        assert_eq!(ini.get("Empty"), None);
+        assert_eq!(ini.get("Missing"), None);
+        assert_eq!(ini.get("Long").map(|l| l.len()), Some(1000));
+        assert_eq!(file.section("Other").get("Name"), None);
+    }
+
+    #[test]
+    fn ini_bools_and_numbers() {
+        let file = TempIni::new("bools");
+        let ini = file.section(SECTION);
+        for (value, expected) in [
+            ("on", Some(true)),
+            ("Yes", Some(true)),
+            ("1", Some(true)),
+            ("OFF", Some(false)),
+            ("false", Some(false)),
+            ("maybe", None),
+        ] {
+            ini.set("Flag", value).unwrap();
+            assert_eq!(ini.get_bool("Flag"), expected, "{}", value);
+        }
+
+        ini.set("Number", "0x40000000").unwrap();
+        assert_eq!(ini.get_u32("Number"), Some(0x4000_0000));
+        ini.set("Number", "42").unwrap();
+        assert_eq!(ini.get_u32("Number"), Some(42));
+        ini.set("Number", "forty-two").unwrap();
+        assert_eq!(ini.get_u32("Number"), None);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let file = TempIni::new("round-trip");
+        let ini = file.section(SECTION);
+
+        let mut s = state();
+        let mut board = Board::new("");
+        board.filename = Some(PathBuf::from(r"C:\firmware\demo.bin"));
+        board.addr = 0x4000_0000;
+        board.jump_addr = Some(0x4000_1000);
+        board.csr_json = Some(PathBuf::from(r"C:\firmware\csr.json"));
+        board.region = Some("main_ram".to_string());
+        board.symbols = BTreeMap::from([("dtb".to_string(), 0x40f0_0000)]);
+        board.protocol = custom_protocol(Profile::CUSTOM);
+        board.apply(&mut s);
+        save_settings(&ini, &s).unwrap();
+
+        let mut loaded = state();
+        load_settings(&ini, &mut loaded);
+        assert_eq!(Board::from_state("", &loaded), board);
+        assert!(!loaded.armed());
+    }
+
+    #[test]
+    fn active_arms_with_a_file() {
+        let file = TempIni::new("active");
+        let ini = file.section(SECTION);
+        ini.set_bool("Active", true).unwrap();
+
+        let mut s = state();
+        load_settings(&ini, &mut s);
+        assert!(!s.armed(), "armed without a file");
+
+        ini.set("File", r"C:\firmware\demo.bin").unwrap();
+        load_settings(&ini, &mut s);
+        assert!(s.armed());
+    }
+
+    #[test]
+    fn boards_and_protocols_round_trip() {
+        let file = TempIni::new("boards");
+        let ini = file.section(SECTION);
+
+        let mut s = state();
+        let mut arty = Board::new("Arty");
+        arty.addr = 0x4000_0000;
+        arty.protocol = custom_protocol("flterm");
+        let mut ulx3s = Board::new("ULX3S");
+        ulx3s.filename = Some(PathBuf::from("ulx3s.bin"));
+        s.protocols = vec![custom_protocol("flterm")];
+        s.boards = vec![arty, ulx3s];
+        s.port_boards = HashMap::from([(3, "Arty".to_string()), (7, "ULX3S".to_string())]);
+        s.board = Some("Arty".to_string());
+        save_settings(&ini, &s).unwrap();
+
+        let mut loaded = state();
+        load_settings(&ini, &mut loaded);
+        assert_eq!(loaded.protocols, s.protocols);
+        assert_eq!(loaded.boards, s.boards);
+        assert_eq!(loaded.port_boards, s.port_boards);
+        assert_eq!(loaded.board, s.board);
+
+        // Deleted boards and protocols don't come back.
+        s.boards.truncate(1);
+        s.port_boards.remove(&7);
+        s.protocols.clear();
+        s.boards[0].protocol = Profile::litex();
+        save_settings(&ini, &s).unwrap();
+        assert_eq!(file.section("TTXLiteX Board 2").get("Name"), None);
+        assert_eq!(file.section("TTXLiteX Protocol 1").get("Name"), None);
+
+        load_settings(&ini, &mut loaded);
+        assert_eq!(loaded.boards, s.boards);
+        assert!(loaded.protocols.is_empty());
+    }
+
+    #[test]
+    fn bad_protocols_are_skipped() {
+        let file = TempIni::new("bad-protocols");
+        let ini = file.section(SECTION);
+        ini.set("ProtocolCount", "4").unwrap();
+        file.section("TTXLiteX Protocol 1")
+            .set("Name", Profile::LITEX)
+            .unwrap();
+        file.section("TTXLiteX Protocol 2")
+            .set("MaxPayload", "64")
+            .unwrap();
+        for i in [3, 4] {
+            let section = file.section(&format!("TTXLiteX Protocol {}", i));
+            section.set("Name", "flterm").unwrap();
+            section.set("MaxPayload", &(60 + i).to_string()).unwrap();
+        }
+        ini.set("Protocol", "nonsense").unwrap();
+
+        let mut s = state();
+        load_settings(&ini, &mut s);
+        assert_eq!(s.protocols.len(), 1);
+        assert_eq!(s.protocols[0].name, "flterm");
+        assert_eq!(s.protocols[0].max_payload, 63);
+        assert_eq!(s.protocol, Profile::litex());
+    }
+
+    #[test]
+    fn bad_custom_protocol_values_are_ignored() {
+        let file = TempIni::new("bad-custom");
+        let ini = file.section(SECTION);
+        ini.set("Protocol", Profile::CUSTOM).unwrap();
+        ini.set("Magic", "").unwrap();
+        ini.set("Response", r"\xZZ").unwrap();
+        ini.set("MaxPayload", "4").unwrap();
+        ini.set("Jump", "sometimes").unwrap();
+
+        let mut s = state();
+        load_settings(&ini, &mut s);
+        assert_eq!(s.protocol, Profile::custom());
+    }
+
+    #[test]
+    fn symbols() {
+        let symbols = parse_symbols("dtb=0x40f00000, rootfs = 0x41000000,,");
+        assert_eq!(
+            symbols,
+            BTreeMap::from([
+                ("dtb".to_string(), 0x40f0_0000),
+                ("rootfs".to_string(), 0x4100_0000),
+            ])
+        );
+
+        let symbols = parse_symbols("=1, bad, worse=xyz, ok=0x10+1");
+        assert_eq!(symbols, BTreeMap::from([("ok".to_string(), 0x11)]));
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn params() {
+        let cmdline = wide(r#" /C=3  /litex-file="C:\My Firmware\demo.bin"	/litex-arm"#);
+        let params = split_params(&cmdline);
+        let text: Vec<_> = params.iter().map(|(_, p)| p.as_str()).collect();
+        assert_eq!(
+            text,
+            ["/C=3", r"/litex-file=C:\My Firmware\demo.bin", "/litex-arm"]
+        );
+        assert_eq!(params[0].0, 1..5);
+        assert_eq!(params[2].0.end, cmdline.len());
+
+        assert!(split_params(&wide("   ")).is_empty());
+        assert_eq!(split_params(&wide(r#""a b"c"#))[0].1, "a bc");
+    }
+
+    #[test]
+    fn cmdline_opts() {
+        let mut cmdline = wide(
+            r#"/C=3 /LITEX-FILE="C:\My Firmware\demo.bin" /litex-addr=0x40000000+1M /litex-jump=4K /litex-arm /BAUD=115200"#,
+        );
+        let mut errors = Vec::new();
+        let opts = take_cmdline_opts(&mut cmdline, &mut errors);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(opts.file, Some(PathBuf::from(r"C:\My Firmware\demo.bin")));
+        assert_eq!(opts.addr, Some(0x4010_0000));
+        assert_eq!(opts.jump_addr, Some(0x1000));
+        assert!(opts.arm);
+
+        // Only Tera Term's own options are left for it to see.
+        let left = String::from_utf16(&cmdline).unwrap();
+        assert_eq!(
+            left.split_whitespace().collect::<Vec<_>>(),
+            ["/C=3", "/BAUD=115200"]
+        );
+    }
+
+    #[test]
+    fn bad_cmdline_opts() {
+        let mut cmdline = wide(
+            "/C=3 /litex-addr=main_ram /litex-jump=0x40000000+ /litex-arm=yes /litex-file \
+             /litex-addr /LITEX-ADR=0x40000000 /litex-other=1 /BAUD=115200",
+        );
+        let mut errors = Vec::new();
+        let opts = take_cmdline_opts(&mut cmdline, &mut errors);
+
+        assert_eq!(errors.len(), 7, "{:?}", errors);
+        assert!(matches!(
+            &errors[2],
+            Error::BadOption { name, value } if name == "/litex-arm" && value == "yes"
+        ));
+        assert!(matches!(
+            &errors[5],
+            Error::BadOption { name, value } if name == "/litex-adr" && value == "0x40000000"
+        ));
+        assert_eq!(opts.addr, None);
+        assert_eq!(opts.jump_addr, None);
+        assert!(!opts.arm);
+        assert_eq!(opts.file, None);
+
+        // Bad and misspelled options are removed too, so Tera Term never
+        // sees them.
+        let left = String::from_utf16(&cmdline).unwrap();
+        assert_eq!(
+            left.split_whitespace().collect::<Vec<_>>(),
+            ["/C=3", "/BAUD=115200"]
+        );
+    }
+
+    #[test]
+    fn addrs() {
+        assert_eq!(parse_addr("0x40000000").unwrap(), 0x4000_0000);
+        assert_eq!(parse_addr("0x40000000 + 0x100").unwrap(), 0x4000_0100);
+        assert!(parse_addr("main_ram").is_err());
+        assert!(parse_addr("").is_err());
+    }
+
+    #[test]
+    fn bools() {
+        for on in ["on", "YES", "true", "1"] {
+            assert!(parse_bool("TTX_LITEX_ARM", on).unwrap(), "{}", on);
+        }
+        for off in ["off", "No", "false", "0", ""] {
+            assert!(!parse_bool("TTX_LITEX_ARM", off).unwrap(), "{}", off);
+        }
+        assert!(matches!(
+            parse_bool("TTX_LITEX_ARM", "maybe"),
+            Err(Error::BadOption { name, value }) if name == "TTX_LITEX_ARM" && value == "maybe"
+        ));
+    }
+
+    /// Look variables up in `vars` instead of the environment.
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        |name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn env_vars() {
+        let vars = env(&[
+            ("TTX_LITEX_KERNEL", r"C:\firmware\demo.bin"),
+            ("TTX_LITEX_ADDRESS", "0x40000000"),
+            ("TTX_LITEX_JUMP_ADDRESS", "0x40000000 + 4K"),
+            ("TTX_LITEX_ARM", "yes"),
+        ]);
+        let mut errors = Vec::new();
+        let opts = overrides_from(vars, &mut errors);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(opts.file, Some(PathBuf::from(r"C:\firmware\demo.bin")));
+        assert_eq!(opts.addr, Some(0x4000_0000));
+        assert_eq!(opts.jump_addr, Some(0x4000_1000));
+        assert!(opts.arm);
+
+        let opts = overrides_from(env(&[]), &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(opts.file, None);
+        assert_eq!(opts.addr, None);
+        assert!(!opts.arm);
+    }
+
+    #[test]
+    fn bad_env_vars() {
+        let vars = env(&[
+            ("TTX_LITEX_ADDRESS", "main_ram"),
+            ("TTX_LITEX_JUMP_ADDRESS", "0x4000_0000"),
+            ("TTX_LITEX_ARM", "maybe"),
+        ]);
+        let mut errors = Vec::new();
+        let opts = overrides_from(vars, &mut errors);
+
+        // One bad variable doesn't spoil the others.
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(opts.addr, None);
+        assert_eq!(opts.jump_addr, Some(0x4000_0000));
+        assert!(!opts.arm);
+    }
+
+    #[test]
+    fn log_levels() {
+        let mut errors = Vec::new();
+        assert_eq!(
+            log_level_from("debug".to_string(), &mut errors),
+            Some(Some(Level::Debug))
+        );
+        assert_eq!(
+            log_level_from("WARN".to_string(), &mut errors),
+            Some(Some(Level::Warn))
+        );
+        assert_eq!(log_level_from("Off".to_string(), &mut errors), Some(None));
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(log_level_from("loud".to_string(), &mut errors), None);
+        assert_eq!(errors.len(), 1);
+    }
+}
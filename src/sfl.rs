@@ -2,6 +2,10 @@
 
 pub mod frame;
 pub mod loader;
+pub mod profile;
+pub mod receive;
 
 pub use frame::*;
 pub use loader::*;
+pub use profile::Profile;
+pub use receive::*;
@@ -8,21 +8,20 @@ pub const MAGIC: &'static [u8] = b"sL5DdSMmkekro\n";
 pub const MAGIC_RESPONSE: &'static [u8] = b"z6IHG7cYDID6o\n";
 
 pub struct MagicMatcher {
-    magic: &'static [u8],
+    magic: Vec<u8>,
     state: usize,
 }
 
-#[derive(IntoBytes, Immutable, Debug, Clone, Copy)]
+#[derive(IntoBytes, Immutable, Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Cmd {
-    #[allow(unused)]
     Abort = 0,
     Load = 1,
     Jump = 2,
 }
 
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Resp {
     Success = b'K',
     CrcError = b'C',
@@ -82,8 +81,11 @@ impl Frame {
 }
 
 impl MagicMatcher {
-    pub fn new(magic: &'static [u8]) -> Self {
-        Self { magic, state: 0 }
+    pub fn new(magic: &[u8]) -> Self {
+        Self {
+            magic: magic.to_vec(),
+            state: 0,
+        }
     }
 
     pub fn look_for_match(&mut self, chunk: &[u8]) -> bool {
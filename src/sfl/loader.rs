@@ -8,16 +8,50 @@ use std::mem::offset_of;
 use std::path::Path;
 
 use crc;
+use sha2::{Digest, Sha256};
 const CCITT: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
 
 pub struct SflLoader<R> {
-    reader: R,
-    base: u32,
+    segments: Vec<Segment<R>>,
     #[allow(unused)]
     offs: usize,
     pub chunk_size: u16,
 }
 
+/// One image to upload, and where it goes in the receiver's memory.
+struct Segment<R> {
+    reader: R,
+    base: u32,
+    /// Filled in on first use, so that constructing a loader can't fail.
+    len: Option<u64>,
+}
+
+/// Calibration never shrinks the chunk size below this.
+pub const MIN_CHUNK_SIZE: u16 = 16;
+
+/// Bytes a frame spends on its length, CRC and command.
+pub const FRAME_HEADER_LEN: u64 = 4;
+/// Bytes a `Load` frame spends on the address before its data.
+pub const LOAD_ADDR_LEN: u64 = 4;
+/// Size of a `Jump` frame on the wire.
+pub const JUMP_FRAME_LEN: u64 = FRAME_HEADER_LEN + 4;
+
+/// Number of `Load` frames needed to send an image of `len` bytes.
+pub fn frame_count(len: u64, chunk_size: u16) -> u64 {
+    len.div_ceil(chunk_size as u64)
+}
+
+/// Bytes sent on the wire for the `Load` frames of an image of `len` bytes.
+pub fn load_wire_len(len: u64, chunk_size: u16) -> u64 {
+    len + frame_count(len, chunk_size) * (FRAME_HEADER_LEN + LOAD_ADDR_LEN)
+}
+
+/// Whether an image of `len` bytes at `base` ends within the receiver's
+/// 32-bit address space.
+pub fn image_fits(base: u32, len: u64) -> bool {
+    u64::from(base) + len <= 1 << 32
+}
+
 impl SflLoader<File> {
     pub fn open<P>(path: P, base: u32) -> Result<SflLoader<File>, io::Error>
     where
@@ -25,23 +59,130 @@ impl SflLoader<File> {
     {
         Ok(SflLoader::new(File::open(path)?, base))
     }
+
+    /// Also upload the file at `path`, after everything added so far.
+    pub fn add_file<P>(&mut self, path: P, base: u32) -> Result<(), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        self.add_image(File::open(path)?, base);
+        Ok(())
+    }
 }
 
 impl<R> SflLoader<R> {
     pub fn new(reader: R, base: u32) -> Self {
         Self {
-            reader,
-            base,
+            segments: vec![Segment {
+                reader,
+                base,
+                len: None,
+            }],
             offs: 0,
             chunk_size: 251,
         }
     }
 
+    /// Also upload `reader` at `base`, after everything added so far. Frame
+    /// numbers continue where the previous image left off.
+    pub fn add_image(&mut self, reader: R, base: u32) {
+        self.segments.push(Segment {
+            reader,
+            base,
+            len: None,
+        });
+    }
+
+    fn segment_len(&mut self, idx: usize) -> Result<u64, io::Error>
+    where
+        R: Seek,
+    {
+        let seg = &mut self.segments[idx];
+        if let Some(len) = seg.len {
+            return Ok(len);
+        }
+
+        let len = seg.reader.seek(SeekFrom::End(0))?;
+        seg.len = Some(len);
+        Ok(len)
+    }
+
+    /// Total number of bytes in all images.
+    pub fn total_len(&mut self) -> Result<u64, io::Error>
+    where
+        R: Seek,
+    {
+        (0..self.segments.len()).map(|i| self.segment_len(i)).sum()
+    }
+
+    /// Base address and length of each image, in upload order.
+    pub fn images(&mut self) -> Result<Vec<(u32, u64)>, io::Error>
+    where
+        R: Seek,
+    {
+        (0..self.segments.len())
+            .map(|i| Ok((self.segments[i].base, self.segment_len(i)?)))
+            .collect()
+    }
+
+    /// SHA-256 of every image and where it goes, to tell whether two
+    /// uploads would send the same thing.
+    pub fn digest(&mut self) -> Result<[u8; 32], io::Error>
+    where
+        R: Read + Seek,
+    {
+        let mut sha = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        for i in 0..self.segments.len() {
+            let len = self.segment_len(i)?;
+            let seg = &mut self.segments[i];
+            sha.update(seg.base.to_be_bytes());
+            sha.update(len.to_be_bytes());
+
+            seg.reader.seek(SeekFrom::Start(0))?;
+            loop {
+                let n = seg.reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                sha.update(&buf[..n]);
+            }
+        }
+
+        Ok(sha.finalize().into())
+    }
+
+    /// Number of Load frames needed at the current chunk size. Each image
+    /// starts on a fresh frame.
+    pub fn total_frames(&mut self) -> Result<u32, io::Error>
+    where
+        R: Seek,
+    {
+        let mut frames = 0;
+        for i in 0..self.segments.len() {
+            frames += frame_count(self.segment_len(i)?, self.chunk_size) as u32;
+        }
+
+        Ok(frames)
+    }
+
+    /// Start calibration from a smaller chunk size, for receivers which
+    /// can't take a full 255-byte payload.
+    pub fn max_chunk_size(mut self, max: u16) -> Self {
+        self.chunk_size = max.clamp(1, 251);
+        self
+    }
+
     pub fn halve_chunk_size(&mut self) {
-        if self.chunk_size == 251 {
-            self.chunk_size = 128;
-        } else if self.chunk_size > 16 {
+        if self.chunk_size <= MIN_CHUNK_SIZE {
+            return;
+        }
+
+        // Odd sizes like 251 round down to a power of two first.
+        if self.chunk_size.is_power_of_two() {
             self.chunk_size /= 2;
+        } else {
+            self.chunk_size = 1 << (u16::BITS - 1 - self.chunk_size.leading_zeros());
         }
     }
 
@@ -56,18 +197,44 @@ impl<R> SflLoader<R> {
             payload: [0; 255],
         });
 
-        let addr = self.base + frame_num * (self.chunk_size as u32);
+        // Find which image this frame belongs to.
+        let chunk_size = self.chunk_size as u64;
+        let mut seg_frame = frame_num as u64;
+        let mut seg_idx = 0;
+        loop {
+            if seg_idx >= self.segments.len() {
+                return Ok(None);
+            }
+
+            let seg_frames = frame_count(self.segment_len(seg_idx)?, self.chunk_size);
+            if seg_frame < seg_frames {
+                break;
+            }
+
+            seg_frame -= seg_frames;
+            seg_idx += 1;
+        }
+
+        let len = self.segment_len(seg_idx)?;
+        let seg = &mut self.segments[seg_idx];
+        if !image_fits(seg.base, len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "image of {} bytes at {:#010x} runs past the end of memory",
+                    len, seg.base
+                ),
+            ));
+        }
+        let offset = seg_frame * chunk_size;
+        let addr = seg.base + offset as u32;
 
         let addr_be = addr.to_be_bytes();
         frame.payload[0..4].copy_from_slice(&addr_be);
         frame.len = 4;
 
-        // XXX: This will seek past the end on last iteration. Works fine on
-        // Windows, but should probably be careful.
-        self.reader.seek(SeekFrom::Start(
-            (frame_num * (self.chunk_size as u32)).into(),
-        ))?;
-        let read_len = self
+        seg.reader.seek(SeekFrom::Start(offset))?;
+        let read_len = seg
             .reader
             .read(&mut frame.payload[4..((self.chunk_size + 4) as usize)])?;
         if read_len == 0 {
@@ -98,6 +265,128 @@ impl<R> SflLoader<R> {
 
         frame
     }
+
+    /// Tell the receiver to give up on the transfer.
+    pub fn encode_abort_frame(&mut self) -> Box<Frame> {
+        let mut frame = Box::new(Frame {
+            len: 0,
+            crc: 0.into(),
+            cmd: Cmd::Abort,
+            payload: [0; 255],
+        });
+
+        let crc = CCITT.checksum(&frame.as_bytes()[offset_of!(Frame, cmd)..]);
+        frame.crc = crc.into();
+
+        frame
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Address and data of a `Load` frame.
+    fn load(frame: &Frame) -> (u32, Vec<u8>) {
+        let bytes = frame.as_bytes();
+        let addr = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        (addr, bytes[8..].to_vec())
+    }
+
+    /// Every `Load` frame the loader sends, in order.
+    fn frames(loader: &mut SflLoader<Cursor<Vec<u8>>>) -> Vec<(u32, Vec<u8>)> {
+        let mut frames = Vec::new();
+        while let Some(frame) = loader.encode_data_frame(frames.len() as u32).unwrap() {
+            frames.push(load(&frame));
+        }
+        frames
+    }
+
+    fn image(len: usize, fill: u8) -> Cursor<Vec<u8>> {
+        Cursor::new(vec![fill; len])
+    }
+
+    #[test]
+    fn short_last_frame() {
+        let mut loader = SflLoader::new(image(40, 0xaa), 0x4000_0000).max_chunk_size(16);
+        assert_eq!(loader.total_frames().unwrap(), 3);
+
+        let frames = frames(&mut loader);
+        assert_eq!(
+            frames,
+            [
+                (0x4000_0000, vec![0xaa; 16]),
+                (0x4000_0010, vec![0xaa; 16]),
+                (0x4000_0020, vec![0xaa; 8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_continue_across_images() {
+        let mut loader = SflLoader::new(image(20, 0xaa), 0x1000).max_chunk_size(16);
+        loader.add_image(image(32, 0xbb), 0x2000);
+        loader.add_image(image(5, 0xcc), 0x3000);
 
+        assert_eq!(loader.total_len().unwrap(), 57);
+        assert_eq!(loader.total_frames().unwrap(), 5);
+        assert_eq!(
+            loader.images().unwrap(),
+            [(0x1000, 20), (0x2000, 32), (0x3000, 5)]
+        );
+
+        // Each image starts on a fresh frame, and ends on a short one if
+        // it doesn't fill it.
+        let frames = frames(&mut loader);
+        assert_eq!(
+            frames,
+            [
+                (0x1000, vec![0xaa; 16]),
+                (0x1010, vec![0xaa; 4]),
+                (0x2000, vec![0xbb; 16]),
+                (0x2010, vec![0xbb; 16]),
+                (0x3000, vec![0xcc; 5]),
+            ]
+        );
+        assert!(loader.encode_data_frame(99).unwrap().is_none());
+    }
+
+    #[test]
+    fn frame_crc() {
+        let mut loader = SflLoader::new(image(4, 0x5a), 0x4000_0000);
+        let frame = loader.encode_data_frame(0).unwrap().unwrap();
+        let bytes = frame.as_bytes();
+
+        assert_eq!(bytes[0], 8);
+        assert_eq!(bytes[3], Cmd::Load as u8);
+        assert_eq!(
+            u16::from_be_bytes([bytes[1], bytes[2]]),
+            CCITT.checksum(&bytes[3..])
+        );
+    }
+
+    #[test]
+    fn digest_covers_data_and_addresses() {
+        let digest = |base: u32, fill: u8| {
+            let mut loader = SflLoader::new(image(20, 0xaa), 0x1000);
+            loader.add_image(image(20, fill), base);
+            loader.digest().unwrap()
+        };
+
+        assert_eq!(digest(0x2000, 0xbb), digest(0x2000, 0xbb));
+        assert_ne!(digest(0x2000, 0xbb), digest(0x2100, 0xbb));
+        assert_ne!(digest(0x2000, 0xbb), digest(0x2000, 0xcc));
+    }
+
+    #[test]
+    fn image_past_end_of_memory() {
+        assert!(image_fits(0xffff_fff0, 16));
+        assert!(!image_fits(0xffff_fff0, 17));
+        assert!(image_fits(0, 1 << 32));
+
+        let mut loader = SflLoader::new(image(32, 0xaa), 0xffff_fff0).max_chunk_size(16);
+        assert!(loader.encode_data_frame(0).is_err());
+        assert!(loader.encode_data_frame(1).is_err());
+    }
+}
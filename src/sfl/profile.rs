@@ -0,0 +1,188 @@
+/*! SFL protocol profiles.
+
+The LiteX BIOS is not the only SFL receiver in the wild. Older MiSoC-era
+`flterm` derivatives and custom ROMs use the same framing, but may use
+different handshake strings, may not understand every command, or may have
+smaller receive buffers. A profile bundles those differences together. */
+
+use std::fmt::Write;
+
+use super::frame::{Cmd, MAGIC, MAGIC_RESPONSE};
+
+/// Largest payload (address + data) that fits into a [`Frame`](super::Frame).
+pub const MAX_PAYLOAD: u8 = 255;
+/// Smallest payload we accept; a `Load` needs at least the 4-byte address
+/// and some data.
+pub const MIN_PAYLOAD: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub magic: Vec<u8>,
+    pub response: Vec<u8>,
+    pub commands: Vec<Cmd>,
+    pub max_payload: u8,
+}
+
+impl Profile {
+    pub const LITEX: &'static str = "LiteX";
+    pub const CUSTOM: &'static str = "Custom";
+
+    /// Handshake and limits used by the LiteX BIOS and `litex_term`.
+    pub fn litex() -> Self {
+        Self {
+            name: Self::LITEX.to_string(),
+            magic: MAGIC.to_vec(),
+            response: MAGIC_RESPONSE.to_vec(),
+            commands: vec![Cmd::Abort, Cmd::Load, Cmd::Jump],
+            max_payload: MAX_PAYLOAD,
+        }
+    }
+
+    /// User-defined profile. Starts out as a copy of the LiteX profile so
+    /// that only the differences need to be filled in.
+    pub fn custom() -> Self {
+        Self {
+            name: Self::CUSTOM.to_string(),
+            ..Self::litex()
+        }
+    }
+
+    /// Profiles shown in the setup dialog, in order. The last entry is
+    /// always the user-editable one.
+    pub fn builtin() -> Vec<Profile> {
+        vec![Self::litex(), Self::custom()]
+    }
+
+    pub fn by_name(name: &str) -> Option<Profile> {
+        Self::builtin().into_iter().find(|p| p.name == name)
+    }
+
+    /// [`builtin`](Self::builtin) profiles with the user's named ones
+    /// (`user`) before the editable one.
+    pub fn all(user: &[Profile]) -> Vec<Profile> {
+        let mut all = Self::builtin();
+        let custom = all.pop();
+        all.extend(user.iter().cloned());
+        all.extend(custom);
+        all
+    }
+
+    /// Like [`by_name`](Self::by_name), but also looks at the user's named
+    /// profiles.
+    pub fn find(name: &str, user: &[Profile]) -> Option<Profile> {
+        Self::by_name(name).or_else(|| user.iter().find(|p| p.name == name).cloned())
+    }
+
+    pub fn is_custom(&self) -> bool {
+        self.name == Self::CUSTOM
+    }
+
+    pub fn is_builtin(&self) -> bool {
+        Self::by_name(&self.name).is_some()
+    }
+
+    pub fn allows(&self, cmd: Cmd) -> bool {
+        self.commands.contains(&cmd)
+    }
+
+    /// Number of data bytes that fit into a `Load` frame after the address.
+    pub fn max_chunk_size(&self) -> u16 {
+        (self.max_payload.clamp(MIN_PAYLOAD, MAX_PAYLOAD) - 4) as u16
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::litex()
+    }
+}
+
+/// Render a handshake string so that it can be edited in a single-line
+/// text control. The inverse of [`unescape`].
+pub fn escape(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+
+    for b in bytes {
+        match b {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(*b as char),
+            _ => {
+                let _ = write!(out, "\\x{:02x}", b);
+            }
+        }
+    }
+
+    out
+}
+
+/// Parse a handshake string typed by the user. Supports `\n`, `\r`, `\t`,
+/// `\\` and `\xNN` escapes.
+pub fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        match bytes.next()? {
+            b'n' => out.push(b'\n'),
+            b'r' => out.push(b'\r'),
+            b't' => out.push(b'\t'),
+            b'\\' => out.push(b'\\'),
+            b'x' => {
+                let hi = (bytes.next()? as char).to_digit(16)?;
+                let lo = (bytes.next()? as char).to_digit(16)?;
+                out.push((hi * 16 + lo) as u8);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            magic: b"hello\n".to_vec(),
+            ..Profile::custom()
+        }
+    }
+
+    #[test]
+    fn user_profiles_come_before_custom() {
+        let user = [named("flterm"), named("ROM")];
+        let names: Vec<_> = Profile::all(&user).into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["LiteX", "flterm", "ROM", "Custom"]);
+    }
+
+    #[test]
+    fn find_user_profile() {
+        let user = [named("flterm")];
+        assert_eq!(Profile::find("flterm", &user), Some(named("flterm")));
+        assert_eq!(Profile::find("LiteX", &user), Some(Profile::litex()));
+        assert_eq!(Profile::find("ROM", &user), None);
+        assert!(!named("flterm").is_builtin());
+        assert!(Profile::custom().is_builtin());
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let bytes = b"sL5\\Dd\r\n\t\x00\xff";
+        assert_eq!(escape(bytes), "sL5\\\\Dd\\r\\n\\t\\x00\\xff");
+        assert_eq!(unescape(&escape(bytes)).as_deref(), Some(&bytes[..]));
+        assert_eq!(unescape("bad\\q"), None);
+        assert_eq!(unescape("short\\x4"), None);
+    }
+}
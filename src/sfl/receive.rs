@@ -0,0 +1,336 @@
+/*! Telling the receiver's responses apart from everything else it sends.
+
+Mid-transfer, the receiver should only send one response letter per frame.
+But a board that resets mid-upload prints its whole boot banner, whose `C`s,
+`E`s, `U`s and `K`s would otherwise be taken for NAKs and acks, and after the
+Jump frame the firmware itself starts talking. */
+
+use std::mem;
+use std::time::{Duration, Instant};
+
+use super::{MagicMatcher, Resp};
+
+/// For how long after the receiver prints text its response letters are
+/// taken for more of that text.
+pub const OUTPUT_QUIET: Duration = Duration::from_millis(100);
+
+/// What a byte received mid-transfer turned out to be.
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Response(Resp),
+    /// The receiver printing something, or line noise.
+    Output,
+    /// The last byte of a fresh magic string: the receiver restarted and
+    /// has forgotten the transfer.
+    Restarted,
+}
+
+pub struct ResponseFilter {
+    matcher: MagicMatcher,
+    watch_magic: bool,
+    /// When the receiver last printed text.
+    output_at: Option<Instant>,
+}
+
+impl ResponseFilter {
+    pub fn new(magic: &[u8]) -> Self {
+        Self {
+            matcher: MagicMatcher::new(magic),
+            watch_magic: !magic.is_empty(),
+            output_at: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.matcher.reset();
+        self.output_at = None;
+    }
+
+    /// Sort out byte `b`, received at `now`. Every byte goes through the
+    /// magic string matcher first, so a restart is noticed whatever came
+    /// before it.
+    pub fn classify(&mut self, b: u8, now: Instant) -> Received {
+        if self.watch_magic && self.matcher.look_for_match(&[b]) {
+            self.reset();
+            return Received::Restarted;
+        }
+
+        let printing = self
+            .output_at
+            .is_some_and(|t| now.saturating_duration_since(t) < OUTPUT_QUIET);
+        match Resp::try_from(b) {
+            Ok(resp) if !printing => Received::Response(resp),
+            _ => {
+                // Noise shouldn't hold up the acks that follow it.
+                if b.is_ascii_graphic() || b.is_ascii_whitespace() {
+                    self.output_at = Some(now);
+                }
+                Received::Output
+            }
+        }
+    }
+}
+
+/// What came back after the Jump frame.
+#[derive(Debug, PartialEq)]
+pub enum AfterJump {
+    /// The firmware is running; its ack got lost.
+    Booted,
+    /// Neither an ack nor text, e.g. a garbled ack. The firmware may be
+    /// running, or the ack may follow.
+    Garbled,
+    /// Left to [`ResponseFilter`].
+    Responses,
+}
+
+/// Looks at what the receiver sends after the Jump frame, for signs the
+/// firmware started even though the Jump wasn't acknowledged.
+pub struct JumpWatch {
+    /// Looks for the boot pattern, if there is one.
+    boot: Option<MagicMatcher>,
+    magic: Vec<u8>,
+    /// The end of the last read, if it could be the start of the magic
+    /// string.
+    partial: Vec<u8>,
+    /// Something [`AfterJump::Garbled`] came back.
+    pub maybe_booted: bool,
+}
+
+impl JumpWatch {
+    pub fn new(boot_pattern: &[u8], magic: &[u8]) -> Self {
+        Self {
+            boot: (!boot_pattern.is_empty()).then(|| MagicMatcher::new(boot_pattern)),
+            magic: magic.to_vec(),
+            partial: Vec::new(),
+            maybe_booted: false,
+        }
+    }
+
+    pub fn check(&mut self, chunk: &[u8]) -> AfterJump {
+        if let Some(boot) = &mut self.boot {
+            if boot.look_for_match(chunk) {
+                return AfterJump::Booted;
+            }
+        }
+
+        // The receiver restarting instead is handled with the responses,
+        // even if the magic string is split across reads.
+        let mut seen = mem::take(&mut self.partial);
+        seen.extend_from_slice(chunk);
+        let magic = self.magic.as_slice();
+        if !magic.is_empty() && seen.windows(magic.len()).any(|w| w == magic) {
+            return AfterJump::Responses;
+        }
+        let partial = (1..magic.len().min(seen.len() + 1))
+            .rev()
+            .find(|n| seen.ends_with(&magic[..*n]))
+            .unwrap_or(0);
+        self.partial = seen.split_off(seen.len() - partial);
+
+        // A lone ack (or NAK) is the receiver's, and an ack followed by
+        // output finishes the upload the usual way.
+        if chunk.first() == Some(&(Resp::Success as u8)) {
+            return AfterJump::Responses;
+        }
+        if seen
+            .iter()
+            .any(|b| b.is_ascii_graphic() && Resp::try_from(*b).is_err())
+        {
+            return AfterJump::Booted;
+        }
+        if seen.iter().any(|b| Resp::try_from(*b).is_err()) {
+            self.maybe_booted = true;
+            return AfterJump::Garbled;
+        }
+
+        AfterJump::Responses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: &[u8] = b"sL5DdSMmkekro\n";
+    const BANNER: &[u8] = b"\x1b[1m        __   _ __      _  __\x1b[0m\n\
+        \n (c) Copyright 2012-2024 Enjoy-Digital\n\
+        \n BIOS CRC passed (8b4be9ac)\n\
+        \n--=============== \x1b[1mSoC\x1b[0m ==================--\n\
+        \x1b[1mCPU\x1b[0m:\t\tVexRiscv @ 100MHz\n\
+        \x1b[1mBUS\x1b[0m:\t\tWishbone 32-bit @ 4GiB\n\
+        \x1b[1mCSR\x1b[0m:\t\t32-bit data\n\
+        \x1b[1mSDRAM\x1b[0m:\t\t128.0MiB 16-bit @ 400MT/s (CL-6 CWL-5)\n\
+        \n--========== \x1b[1mInitialization\x1b[0m ============--\n\
+        Memtest OK\nMemspeed at 0x40000000 (Sequential, 2.0MiB)...\n\
+        \n--============== \x1b[1mBoot\x1b[0m ==================--\n\
+        Booting from serial...\nPress Q or ESC to abort boot completely.\n";
+
+    fn classify_all(filter: &mut ResponseFilter, chunk: &[u8], now: Instant) -> Vec<Received> {
+        chunk.iter().map(|b| filter.classify(*b, now)).collect()
+    }
+
+    fn responses(received: &[Received]) -> usize {
+        received
+            .iter()
+            .filter(|r| matches!(r, Received::Response(_)))
+            .count()
+    }
+
+    #[test]
+    fn acks_are_responses() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let got = classify_all(&mut filter, b"KKCK", Instant::now());
+        assert_eq!(
+            got,
+            [
+                Received::Response(Resp::Success),
+                Received::Response(Resp::Success),
+                Received::Response(Resp::CrcError),
+                Received::Response(Resp::Success),
+            ]
+        );
+    }
+
+    #[test]
+    fn banner_letters_arent_responses() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let t0 = Instant::now();
+
+        let mut got = classify_all(&mut filter, BANNER, t0);
+        got.extend(classify_all(&mut filter, MAGIC, t0));
+        assert_eq!(responses(&got), 0);
+        assert_eq!(got.last(), Some(&Received::Restarted));
+        assert_eq!(got.iter().filter(|r| **r == Received::Restarted).count(), 1);
+    }
+
+    #[test]
+    fn banner_split_across_reads() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let t0 = Instant::now();
+        let mut got = Vec::new();
+        for (i, piece) in BANNER.chunks(7).chain(MAGIC.chunks(5)).enumerate() {
+            let now = t0 + Duration::from_millis(i as u64);
+            got.extend(classify_all(&mut filter, piece, now));
+        }
+
+        assert_eq!(responses(&got), 0);
+        assert_eq!(got.last(), Some(&Received::Restarted));
+    }
+
+    #[test]
+    fn ack_then_restart_in_one_read() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let mut chunk = b"K\r\n".to_vec();
+        chunk.extend_from_slice(MAGIC);
+
+        let got = classify_all(&mut filter, &chunk, Instant::now());
+        assert_eq!(got[0], Received::Response(Resp::Success));
+        assert_eq!(responses(&got), 1);
+        assert_eq!(got.last(), Some(&Received::Restarted));
+    }
+
+    #[test]
+    fn acks_resume_once_output_stops() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let t0 = Instant::now();
+        classify_all(&mut filter, b"Hello\n", t0);
+
+        assert_eq!(filter.classify(b'K', t0), Received::Output);
+        assert_eq!(
+            filter.classify(b'K', t0 + OUTPUT_QUIET * 2),
+            Received::Response(Resp::Success)
+        );
+    }
+
+    #[test]
+    fn noise_doesnt_hold_up_acks() {
+        let mut filter = ResponseFilter::new(MAGIC);
+        let t0 = Instant::now();
+        assert_eq!(filter.classify(0xeb, t0), Received::Output);
+        assert_eq!(filter.classify(b'K', t0), Received::Response(Resp::Success));
+    }
+
+    #[test]
+    fn no_magic_never_restarts() {
+        let mut filter = ResponseFilter::new(b"");
+        let got = classify_all(&mut filter, MAGIC, Instant::now());
+        assert!(!got.contains(&Received::Restarted));
+    }
+
+    #[test]
+    fn lone_ack_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(watch.check(b"K"), AfterJump::Responses);
+        assert!(!watch.maybe_booted);
+    }
+
+    #[test]
+    fn ack_and_output_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(
+            watch.check(b"KHello from firmware\r\n"),
+            AfterJump::Responses
+        );
+    }
+
+    #[test]
+    fn output_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(watch.check(b"Hello from firmware\r\n"), AfterJump::Booted);
+    }
+
+    #[test]
+    fn garbled_ack_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(watch.check(b"\xeb"), AfterJump::Garbled);
+        assert!(watch.maybe_booted);
+        assert_eq!(watch.check(b"Hello from firmware\r\n"), AfterJump::Booted);
+    }
+
+    #[test]
+    fn garbled_ack_and_output_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(
+            watch.check(b"\xebHello from firmware\r\n"),
+            AfterJump::Booted
+        );
+    }
+
+    #[test]
+    fn restart_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        let mut chunk = BANNER.to_vec();
+        chunk.extend_from_slice(MAGIC);
+        assert_eq!(watch.check(&chunk), AfterJump::Responses);
+    }
+
+    #[test]
+    fn boot_pattern_split_across_reads() {
+        let mut watch = JumpWatch::new(b"\r\n\r\n", MAGIC);
+        assert_eq!(watch.check(b"\r\n"), AfterJump::Garbled);
+        assert_eq!(watch.check(b"\r\n"), AfterJump::Booted);
+    }
+
+    #[test]
+    fn boot_pattern_made_of_responses() {
+        let mut watch = JumpWatch::new(b"UKUK", MAGIC);
+        assert_eq!(watch.check(b"UK"), AfterJump::Responses);
+        assert_eq!(watch.check(b"UK"), AfterJump::Booted);
+    }
+
+    #[test]
+    fn restart_split_across_reads_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        let mut chunk = b"\r\n".to_vec();
+        chunk.extend_from_slice(&MAGIC[..5]);
+        assert_eq!(watch.check(&chunk), AfterJump::Garbled);
+        assert_eq!(watch.check(&MAGIC[5..]), AfterJump::Responses);
+    }
+
+    #[test]
+    fn almost_magic_after_jump() {
+        let mut watch = JumpWatch::new(b"", MAGIC);
+        assert_eq!(watch.check(&MAGIC[..5]), AfterJump::Responses);
+        assert_eq!(watch.check(b"x"), AfterJump::Booted);
+    }
+}
@@ -8,35 +8,130 @@ also run in a single thread. So we can use the thread_local macro.
 */
 
 use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs::File;
 use std::path::PathBuf;
 
 use std::ptr;
 use std::time::Instant;
 
-use super::sfl::{self, Frame, MagicMatcher, SflLoader};
+use super::banner::{BannerScanner, BiosInfo};
+use super::board::Board;
+use super::history::History;
+use super::options::TransferOptions;
+use super::plan::Plan;
+use super::progress::RateMeter;
+use super::project::UploadSettings;
+use super::setup::Overrides;
+use super::sfl::{Frame, JumpWatch, MagicMatcher, Profile, ResponseFilter, SflLoader};
+use super::stats::TransferStats;
 use super::tt;
+use super::watch::FileWatch;
 
 use windows::Win32::Foundation::*;
 
 pub struct State {
-    #[allow(unused)]
     pub ts: tt::PTTSet,
     pub cv: tt::PComVar,
     pub orig_readfile: tt::TReadFile,
+    pub orig_read_ini: tt::PReadIniFile,
+    pub orig_write_ini: tt::PWriteIniFile,
+    pub orig_parse_param: tt::PParseParam,
     pub activity: Activity,
+    pub protocol: Profile,
+    /// The user's named protocol profiles.
+    pub protocols: Vec<Profile>,
     pub matcher: MagicMatcher,
     pub sfl_loader: Option<SflLoader<File>>,
+    /// Settings of the upload underway, or of the last one.
+    pub upload: Option<UploadSettings>,
+    /// Sorts out what the receiver sends during a transfer.
+    pub responses: ResponseFilter,
     pub last_frame_sent: Option<u32>,
     pub last_frame_acked: Option<u32>,
     pub filename: Option<PathBuf>,
     pub addr: u32,
-    pub curr_frame: Option<Box<Frame>>,
+    pub jump_addr: Option<u32>,
+    /// See [`Board::csr_json`].
+    pub csr_json: Option<PathBuf>,
+    /// See [`Board::region`].
+    pub region: Option<String>,
+    /// See [`Board::symbols`].
+    pub symbols: BTreeMap<String, u32>,
+    /// Frames sent but not yet acknowledged, oldest first.
+    pub in_flight: VecDeque<InFlight>,
+    pub stats: TransferStats,
+    pub rate: RateMeter,
     pub file_size: Option<u64>,
     pub start_time: Option<Instant>,
+    /// Bytes waiting to be shown in the terminal; see [`crate::io::notice`].
+    pub notices: VecDeque<Vec<u8>>,
+    pub env_overrides: Overrides,
+    pub boards: Vec<Board>,
+    /// Name of the board profile the current settings came from.
+    pub board: Option<String>,
+    /// Board profile last used on each serial port.
+    pub port_boards: HashMap<u16, String>,
+    pub history: History,
+    pub options: TransferOptions,
+    pub banner: BannerScanner,
+    /// What the LiteX BIOS said about itself the last time it booted.
+    pub bios: Option<BiosInfo>,
+    /// While "Upload Now" waits for the magic string, what to go back to if
+    /// it doesn't come.
+    pub trigger_fallback: Option<Activity>,
+    pub watch: FileWatch,
+    /// Successful uploads left before disarming, or `None` for no limit.
+    pub uploads_left: Option<u32>,
+    /// Disarm if no upload has started by then.
+    pub armed_until: Option<Instant>,
+    /// Asking the user whether to upload; the magic string is ignored
+    /// meanwhile.
+    pub confirming: bool,
+    /// The user already agreed to the next upload, so don't ask again.
+    pub upload_approved: bool,
+    /// What the user was shown when they agreed to the next upload, to be
+    /// sent as-is. `None` if they asked for it with "Upload Now".
+    pub approved_plan: Option<Plan>,
+    /// [`SflLoader::digest`] of the upload underway.
+    pub upload_digest: Option<[u8; 32]>,
+    /// Where the last interrupted upload got to.
+    pub resume: Option<ResumePoint>,
+    /// The LiteX BIOS has booted since the upload underway started.
+    pub rebooted_mid_upload: bool,
+    /// Looks at what comes back after the Jump frame.
+    pub jump_watch: JumpWatch,
 }
 
-#[derive(PartialEq, Clone, Copy)]
+/// Where an interrupted upload got to, so the next one can carry on from
+/// there if it would send the same thing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumePoint {
+    /// [`SflLoader::digest`] of the interrupted upload.
+    pub digest: [u8; 32],
+    /// Frame numbers only line up at the same chunk size.
+    pub chunk_size: u16,
+    /// First frame the receiver hadn't acknowledged.
+    pub next_frame: u32,
+    /// The LiteX BIOS has booted since, and tested (overwritten) its RAM.
+    pub rebooted: bool,
+}
+
+/// A frame waiting for the receiver's acknowledgment.
+pub struct InFlight {
+    /// `None` for the final `Jump` frame.
+    pub num: Option<u32>,
+    pub frame: Box<Frame>,
+    /// Times this frame has been resent.
+    pub retries: u32,
+    /// When this frame was last sent.
+    pub sent_at: Instant,
+}
+
+/// Boot address until the user says otherwise.
+pub const DEFAULT_ADDR: u32 = 0x40000000;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Activity {
     Inactive,
     LookForMagic,
@@ -45,21 +140,117 @@ pub enum Activity {
     WaitFinalResp,
 }
 
+impl Activity {
+    /// Whether an SFL transfer is underway, as opposed to idle or waiting for
+    /// the magic string.
+    pub fn in_transfer(&self) -> bool {
+        matches!(
+            self,
+            Activity::Calibrate | Activity::WaitResp | Activity::WaitFinalResp
+        )
+    }
+}
+
+impl State {
+    pub fn new(ts: tt::PTTSet, cv: tt::PComVar) -> Self {
+        let protocol = Profile::litex();
+        let matcher = MagicMatcher::new(&protocol.magic);
+
+        State {
+            ts,
+            cv,
+            orig_readfile: None,
+            orig_read_ini: None,
+            orig_write_ini: None,
+            orig_parse_param: None,
+            activity: Activity::Inactive,
+            protocol,
+            protocols: Vec::new(),
+            matcher,
+            sfl_loader: None,
+            upload: None,
+            responses: ResponseFilter::new(&[]),
+            last_frame_acked: None,
+            last_frame_sent: None,
+            filename: None,
+            addr: DEFAULT_ADDR,
+            jump_addr: None,
+            csr_json: None,
+            region: None,
+            symbols: BTreeMap::new(),
+            in_flight: VecDeque::new(),
+            stats: TransferStats::default(),
+            rate: RateMeter::default(),
+            file_size: None,
+            start_time: None,
+            notices: VecDeque::new(),
+            env_overrides: Overrides::default(),
+            boards: Vec::new(),
+            board: None,
+            port_boards: HashMap::new(),
+            history: History::default(),
+            options: TransferOptions::default(),
+            banner: BannerScanner::default(),
+            bios: None,
+            trigger_fallback: None,
+            watch: FileWatch::default(),
+            uploads_left: None,
+            armed_until: None,
+            confirming: false,
+            upload_approved: false,
+            approved_plan: None,
+            upload_digest: None,
+            resume: None,
+            rebooted_mid_upload: false,
+            jump_watch: JumpWatch::new(&[], &[]),
+        }
+    }
+
+    /// Start looking for the magic string from a clean slate.
+    pub fn arm(&mut self) {
+        self.matcher.reset();
+        self.activity = Activity::LookForMagic;
+        self.last_frame_acked = None;
+        self.last_frame_sent = None;
+        self.in_flight.clear();
+        self.stats = TransferStats::default();
+        self.uploads_left = self.options.arm_mode.uploads();
+        self.forget_approval();
+        self.restart_arm_expiry();
+    }
+
+    /// Stop looking for the magic string.
+    pub fn disarm(&mut self) {
+        self.activity = Activity::Inactive;
+        self.armed_until = None;
+        self.forget_approval();
+    }
+
+    /// Ask again before the next upload.
+    pub fn forget_approval(&mut self) {
+        self.upload_approved = false;
+        self.approved_plan = None;
+    }
+
+    /// Give the user another [`arm_expiry`] to start an upload.
+    ///
+    /// [`arm_expiry`]: TransferOptions::arm_expiry
+    pub fn restart_arm_expiry(&mut self) {
+        self.armed_until = self.options.arm_expiry.map(|t| Instant::now() + t);
+    }
+
+    pub fn arm_expired(&self) -> bool {
+        self.armed_until.is_some_and(|t| Instant::now() >= t)
+    }
+
+    /// Whether the plugin will upload when it sees the magic string.
+    pub fn armed(&self) -> bool {
+        self.activity == Activity::LookForMagic && !self.arm_expired()
+    }
+}
+
 thread_local! {
     pub static OUR_HINST: Cell<HINSTANCE> = Cell::new(HINSTANCE(ptr::null_mut()));
-    pub static TTX_LITEX_STATE: RefCell<State> = RefCell::new(State {
-        ts: ptr::null_mut(),
-        cv: ptr::null_mut(),
-        orig_readfile: None,
-        activity: Activity::Inactive,
-        matcher: MagicMatcher::new(sfl::MAGIC),
-        sfl_loader: None,
-        last_frame_acked: None,
-        last_frame_sent: None,
-        filename: None,
-        addr: 0x40000000,
-        curr_frame: None,
-        file_size: None,
-        start_time: None
-    });
+    pub static TTX_LITEX_STATE: RefCell<State> =
+        RefCell::new(State::new(ptr::null_mut(), ptr::null_mut()));
 }
@@ -0,0 +1,93 @@
+/*! Statistics about a transfer.
+
+Collected while the transfer runs, and shown as a block of lines when it
+ends if "Detailed report" is checked, to help work out what went wrong with
+a flaky upload. */
+
+use std::time::Duration;
+
+use pretty_bytes_typed::pretty_bytes;
+
+use super::sfl::Resp;
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferStats {
+    /// Chunk size the transfer ended up using.
+    pub chunk_size: u16,
+    /// Whether the chunk size was calibrated, rather than fixed.
+    pub calibrated: bool,
+    /// Times calibration halved the chunk size.
+    pub halvings: u32,
+    /// Frames sent, including resends and the `Jump`.
+    pub frames_sent: u32,
+    /// Frames resent after an error response or a timeout.
+    pub frames_resent: u32,
+    /// `C` responses.
+    pub crc_errors: u32,
+    /// `U` responses.
+    pub unknown_cmds: u32,
+    /// `E` responses.
+    pub ack_errors: u32,
+    pub timeouts: u32,
+    /// Longest time between sending a frame and its acknowledgment.
+    pub max_latency: Duration,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl TransferStats {
+    /// Count an error response from the receiver.
+    pub fn count_error(&mut self, resp: &Resp) {
+        match resp {
+            Resp::CrcError => self.crc_errors += 1,
+            Resp::Unknown => self.unknown_cmds += 1,
+            Resp::AckError => self.ack_errors += 1,
+            Resp::Success => {}
+        }
+    }
+
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    /// The full report, one line per entry.
+    pub fn describe(&self) -> Vec<String> {
+        let secs = self.elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            format!(
+                "{}/s",
+                pretty_bytes((self.bytes as f64 / secs) as u64, Some(2))
+            )
+        } else {
+            "-".to_string()
+        };
+        let chunk = if self.calibrated {
+            format!(
+                "{} bytes (calibrated, halved {} times)",
+                self.chunk_size, self.halvings
+            )
+        } else {
+            format!("{} bytes", self.chunk_size)
+        };
+
+        vec![
+            format!("Elapsed:      {:.1} s", secs),
+            format!(
+                "Sent:         {} ({} bytes), {}",
+                pretty_bytes(self.bytes, Some(2)),
+                self.bytes,
+                rate
+            ),
+            format!("Chunk size:   {}", chunk),
+            format!(
+                "Frames:       {} sent, {} resent",
+                self.frames_sent, self.frames_resent
+            ),
+            format!(
+                "Errors:       {} CRC (C), {} unknown command (U), {} other (E), {} timeouts",
+                self.crc_errors, self.unknown_cmds, self.ack_errors, self.timeouts
+            ),
+            format!("Slowest ack:  {} ms", self.max_latency.as_millis()),
+        ]
+    }
+}
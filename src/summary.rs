@@ -0,0 +1,281 @@
+/*! Summary of what an upload would send.
+
+Shown in the setup dialog before arming, so that a wrong file, a wrong
+address or an unexpectedly slow transfer is caught before the receiver is
+reset. */
+
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use pretty_bytes_typed::pretty_bytes;
+use sha2::{Digest, Sha256};
+
+use super::project::ProjectConfig;
+use super::sfl::{self, Cmd, Profile};
+use super::state::State;
+use super::tt;
+
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Raw,
+    /// LiteX flash boot image: raw binary behind a length and CRC32 header.
+    Fbi,
+    Elf,
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    /// Guess a file's format from its first few bytes.
+    pub fn detect(head: &[u8], len: u64) -> Self {
+        let body_len = len.checked_sub(8).and_then(|l| u32::try_from(l).ok());
+        let is_fbi_header = |h: [u8; 4]| {
+            body_len.is_some_and(|l| u32::from_le_bytes(h) == l || u32::from_be_bytes(h) == l)
+        };
+
+        match head {
+            [0x7f, b'E', b'L', b'F', ..] => Format::Elf,
+            [b':', rest @ ..] if rest.iter().take(8).all(u8::is_ascii_hexdigit) => Format::IntelHex,
+            [b'S', b'0'..=b'9', rest @ ..] if rest.iter().take(8).all(u8::is_ascii_hexdigit) => {
+                Format::SRecord
+            }
+            [a, b, c, d, ..] if is_fbi_header([*a, *b, *c, *d]) => Format::Fbi,
+            _ => Format::Raw,
+        }
+    }
+
+    /// Whether the receiver will get what the user probably meant. SFL
+    /// sends files byte for byte.
+    pub fn is_loadable(&self) -> bool {
+        matches!(self, Format::Raw | Format::Fbi)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Raw => write!(f, "Raw binary"),
+            Format::Fbi => write!(f, "LiteX flash boot image"),
+            Format::Elf => write!(f, "ELF"),
+            Format::IntelHex => write!(f, "Intel HEX"),
+            Format::SRecord => write!(f, "Motorola S-record"),
+        }
+    }
+}
+
+/// One image and where it will be loaded.
+#[derive(Debug)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub base: u32,
+    pub len: u64,
+}
+
+/// Serial line settings, for estimating transfer time.
+#[derive(Debug, Clone, Copy)]
+pub struct Line {
+    pub baud: u32,
+    pub data_bits: u32,
+    pub parity: bool,
+    pub stop_bits: u32,
+}
+
+impl Line {
+    /// Current serial settings, or `None` if Tera Term isn't connected to a
+    /// serial port.
+    pub fn current(s: &State) -> Option<Self> {
+        super::board::com_port(s)?;
+        if s.ts.is_null() {
+            return None;
+        }
+
+        // SAFETY: Assumes TeraTerm passed us a valid pointer in TTXInit.
+        let ts = unsafe { &*s.ts };
+        Some(Self {
+            baud: ts.Baud,
+            data_bits: if ts.DataBit as u32 == tt::IdDataBit7 {
+                7
+            } else {
+                8
+            },
+            parity: ts.Parity as u32 != tt::IdParityNone,
+            stop_bits: if ts.StopBit as u32 == tt::IdStopBit2 {
+                2
+            } else {
+                1
+            },
+        })
+    }
+
+    /// Time to send one character, including start, parity and stop bits.
+    pub fn char_time(&self) -> Duration {
+        let bits = 1 + self.data_bits + self.parity as u32 + self.stop_bits;
+        Duration::from_secs_f64(bits as f64 / self.baud.max(1) as f64)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}{}{}",
+            self.baud,
+            self.data_bits,
+            if self.parity { "P" } else { "N" },
+            self.stop_bits
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    pub format: Format,
+    pub segments: Vec<Segment>,
+    pub crc32: u32,
+    pub sha256: String,
+    pub modified: Option<SystemTime>,
+    /// `Load` frames, plus the `Jump` if the protocol has one.
+    pub frames: u64,
+    /// Bytes sent to the receiver, including framing.
+    pub wire_len: u64,
+    /// Bytes the receiver sends back: the magic string, and an ack for every
+    /// frame.
+    pub reply_len: u64,
+}
+
+impl Summary {
+    /// Summarize uploading `path` to `base`, followed by any extra images
+    /// from `config`. Frames are counted at `chunk_size`; when calibrating,
+    /// that's where calibration starts.
+    pub fn new(
+        path: &Path,
+        base: u32,
+        config: Option<&ProjectConfig>,
+        protocol: &Profile,
+        chunk_size: u16,
+    ) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let meta = file.metadata()?;
+
+        let mut crc = CRC32.digest();
+        let mut sha = Sha256::new();
+        let mut head = Vec::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if head.len() < 16 {
+                head.extend_from_slice(&buf[..n.min(16 - head.len())]);
+            }
+            crc.update(&buf[..n]);
+            sha.update(&buf[..n]);
+        }
+
+        let mut segments = vec![Segment {
+            path: path.to_path_buf(),
+            base,
+            len: meta.len(),
+        }];
+        for (path, base) in config.iter().flat_map(|c| c.image_paths()) {
+            let len = path.metadata()?.len();
+            segments.push(Segment { path, base, len });
+        }
+
+        let mut frames = 0;
+        let mut wire_len = 0;
+        for seg in &segments {
+            frames += sfl::frame_count(seg.len, chunk_size);
+            wire_len += sfl::load_wire_len(seg.len, chunk_size);
+        }
+        if protocol.allows(Cmd::Jump) {
+            frames += 1;
+            wire_len += sfl::JUMP_FRAME_LEN;
+        }
+        wire_len += protocol.response.len() as u64;
+
+        let mut sha256 = String::with_capacity(64);
+        for b in sha.finalize() {
+            let _ = write!(sha256, "{:02x}", b);
+        }
+
+        Ok(Self {
+            format: Format::detect(&head, meta.len()),
+            segments,
+            crc32: crc.finalize(),
+            sha256,
+            modified: meta.modified().ok(),
+            frames,
+            wire_len,
+            reply_len: protocol.magic.len() as u64 + frames,
+        })
+    }
+
+    /// Best-case transfer time, ignoring how long the receiver takes to
+    /// process each frame.
+    pub fn transfer_time(&self, line: &Line) -> Duration {
+        line.char_time()
+            .mul_f64((self.wire_len + self.reply_len) as f64)
+    }
+
+    /// Multi-line description for the setup dialog.
+    pub fn describe(&self, line: Option<&Line>) -> String {
+        let mut out = String::new();
+        let len = self.segments[0].len;
+
+        let _ = write!(out, "Format: {}", self.format);
+        if !self.format.is_loadable() {
+            let _ = write!(out, " (sent as-is; convert to a raw binary first?)");
+        }
+        let _ = write!(
+            out,
+            "\r\nSize: {} ({} bytes)",
+            pretty_bytes(len, Some(2)),
+            len
+        );
+        if let Some(modified) = self.modified {
+            let modified: DateTime<Local> = modified.into();
+            let _ = write!(
+                out,
+                "\r\nModified: {}",
+                modified.format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+        let _ = write!(out, "\r\nCRC32: {:08x}", self.crc32);
+        let _ = write!(out, "\r\nSHA-256: {}", self.sha256);
+
+        for seg in &self.segments {
+            let end = seg.base as u64 + seg.len.max(1) - 1;
+            let name = seg
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let _ = write!(out, "\r\n{:#010x}-{:#010x}  {}", seg.base, end, name);
+        }
+
+        let _ = write!(out, "\r\nFrames: {}", self.frames);
+        match line {
+            Some(line) => {
+                let _ = write!(
+                    out,
+                    ", about {:.1} s at {}",
+                    self.transfer_time(line).as_secs_f64(),
+                    line
+                );
+            }
+            None => {
+                let _ = write!(out, " (not connected to a serial port)");
+            }
+        }
+
+        out
+    }
+}
@@ -1,32 +1,121 @@
 /*! UI and Window-creation hooks. */
 
-use std::ffi::OsString;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_void, OsString};
 use std::fmt::Write;
-use std::os::windows::ffi::{OsStrExt, OsStringExt};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io;
+use std::ops::RangeInclusive;
+use std::os::windows::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use log::*;
 use parse_int::parse;
+use pretty_bytes_typed::pretty_bytes;
 use rfd::FileDialog;
 
-use super::state::{Activity, OUR_HINST, TTX_LITEX_STATE};
+use super::board::{self, Board};
+use super::csr::CsrMap;
+use super::expr::{self, Symbols};
+use super::history::History;
+use super::io::{notice, update_watch, upload_now};
+use super::options::{
+    ArmMode, Color, MessageLevel, TransferOptions, MAX_CHUNK_SIZE, MAX_IN_FLIGHT, TRIGGERS,
+};
+use super::progress::{Progress, StatusStyle};
+use super::project::ProjectConfig;
+use super::setup;
+use super::sfl::profile::{self, MAX_PAYLOAD, MIN_PAYLOAD};
+use super::sfl::{Cmd, Profile};
+use super::state::{Activity, DEFAULT_ADDR, OUR_HINST, TTX_LITEX_STATE};
+use super::summary::{Line, Summary};
 use super::tt;
 use super::Error;
 
-use widestring::{u16cstr, U16CString};
+use widestring::{u16cstr, U16CStr, U16CString};
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::*;
+use windows::Win32::Graphics::Gdi::*;
+use windows::Win32::UI::Input::KeyboardAndMouse::{EnableWindow, SetFocus};
 use windows::Win32::UI::WindowsAndMessaging::*;
 
 pub const ID_MENU_LITEX: usize = 56000;
+pub const ID_MENU_LITEX_UPLOAD_NOW: usize = 56001;
 pub const IDD_SETUP_LITEX: usize = 1001;
 pub const IDC_LITEX_KERNEL: usize = 1002;
 pub const IDC_LITEX_BOOT_ADDR: usize = 1003;
 pub const IDC_LITEX_CHOOSE_KERNEL_BUTTON: usize = 1004;
 pub const IDC_LITEX_ACTIVE: usize = 1005;
+pub const IDC_LITEX_PROTOCOL: usize = 1006;
+pub const IDC_LITEX_MAGIC: usize = 1007;
+pub const IDC_LITEX_MAGIC_RESPONSE: usize = 1008;
+pub const IDC_LITEX_MAX_PAYLOAD: usize = 1009;
+pub const IDC_LITEX_ALLOW_JUMP: usize = 1010;
+pub const IDC_LITEX_JUMP_ADDR: usize = 1011;
+pub const IDC_LITEX_BOARD: usize = 1012;
+pub const IDC_LITEX_BOARD_NEW: usize = 1013;
+pub const IDC_LITEX_BOARD_RENAME: usize = 1014;
+pub const IDC_LITEX_BOARD_DELETE: usize = 1015;
+pub const IDD_LITEX_BOARD_NAME: usize = 1016;
+pub const IDC_LITEX_BOARD_NAME: usize = 1017;
+pub const IDC_LITEX_PROJECT_CONFIG: usize = 1018;
+pub const IDC_LITEX_KERNEL_ERR: usize = 1019;
+pub const IDC_LITEX_BOOT_ADDR_ERR: usize = 1020;
+pub const IDC_LITEX_JUMP_ADDR_ERR: usize = 1021;
+pub const IDC_LITEX_PROTOCOL_ERR: usize = 1022;
+pub const IDC_LITEX_SUMMARY: usize = 1023;
+pub const IDC_LITEX_CHUNK_AUTO: usize = 1024;
+pub const IDC_LITEX_CHUNK_SIZE: usize = 1025;
+pub const IDC_LITEX_MAX_IN_FLIGHT: usize = 1026;
+pub const IDC_LITEX_FRAME_TIMEOUT: usize = 1027;
+pub const IDC_LITEX_MAX_RETRIES: usize = 1028;
+pub const IDC_LITEX_STATUS_BAR: usize = 1029;
+pub const IDC_LITEX_ADVANCED_ERR: usize = 1030;
+pub const IDC_LITEX_TRANSFER_STATUS: usize = 1031;
+pub const IDC_LITEX_PROGRESS_WINDOW: usize = 1032;
+pub const IDD_LITEX_PROGRESS: usize = 1033;
+pub const IDC_LITEX_PROGRESS_BAR: usize = 1034;
+pub const IDC_LITEX_PROGRESS_TEXT: usize = 1035;
+pub const IDC_LITEX_STATUS_COMPACT: usize = 1036;
+pub const IDC_LITEX_DETAILED_REPORT: usize = 1037;
+pub const IDC_LITEX_MESSAGE_LEVEL: usize = 1038;
+pub const IDC_LITEX_MESSAGE_COLOR: usize = 1039;
+pub const IDC_LITEX_CSR_JSON: usize = 1040;
+pub const IDC_LITEX_CHOOSE_CSR_BUTTON: usize = 1041;
+pub const IDC_LITEX_CSR_ERR: usize = 1042;
+pub const IDC_LITEX_REGION: usize = 1043;
+pub const IDC_LITEX_DETECT_BOOT_ADDR: usize = 1044;
+pub const IDC_LITEX_TRIGGER: usize = 1045;
+pub const IDC_LITEX_TRIGGER_TIMEOUT: usize = 1046;
+pub const IDC_LITEX_WATCH_FILE: usize = 1047;
+pub const IDC_LITEX_ARM_MODE: usize = 1048;
+pub const IDC_LITEX_ARM_COUNT: usize = 1049;
+pub const IDC_LITEX_ARM_EXPIRY: usize = 1050;
+pub const IDC_LITEX_CONFIRM_UPLOAD: usize = 1051;
+pub const IDC_LITEX_RESTART_ON_RESET: usize = 1052;
+pub const IDC_LITEX_RESUME_UPLOADS: usize = 1053;
+pub const IDC_LITEX_BOOT_PATTERN: usize = 1054;
+pub const IDC_LITEX_PROTOCOL_SAVE: usize = 1055;
+pub const IDC_LITEX_PROTOCOL_DELETE: usize = 1056;
 
+/// Board combo box entry for settings that don't belong to a board.
+const NO_BOARD: &str = "(None)";
+/// Region combo box entry for "whichever region holds the boot address".
+const ANY_REGION: &str = "(Any)";
+/// Arming combo box entries, in the order [`show_options`] selects them.
+const ARM_MODES: &[&str] = &["Always", "Once", "N Times"];
+
+/// File types offered first by the file picker.
+const KERNEL_EXTENSIONS: &[&str] = &["bin", "fbi", "img"];
+
+// GetWindowTextLengthW and GetDlgItemTextW don't clear the last error on
+// success, so an empty control would otherwise report whatever error some
+// earlier call left behind.
 fn get_buf_len(dialog: HWND, control: i32) -> Result<usize, windows::core::Error> {
     let control_handle = unsafe { GetDlgItem(Some(dialog), control)? };
+    unsafe { SetLastError(WIN32_ERROR(0)) };
     let maybe_buf_len = unsafe { GetWindowTextLengthW(control_handle) };
 
     let maybe_error = windows::core::Error::from_win32();
@@ -40,6 +129,7 @@ fn get_buf_len(dialog: HWND, control: i32) -> Result<usize, windows::core::Error
 fn get_dlg_osstring(dialog: HWND, control: i32) -> Result<OsString, windows::core::Error> {
     let code_unit_len = get_buf_len(dialog, control)?;
     let mut code_str: Vec<u16> = vec![0; code_unit_len + 1];
+    unsafe { SetLastError(WIN32_ERROR(0)) };
     let used_len = unsafe { GetDlgItemTextW(dialog, control, &mut code_str) } as usize;
 
     // We don't need the null terminator.
@@ -52,11 +142,1277 @@ fn get_dlg_osstring(dialog: HWND, control: i32) -> Result<OsString, windows::cor
     }
 }
 
+fn set_dlg_str(dialog: HWND, control: i32, text: &str) -> Result<(), windows::core::Error> {
+    let wide = U16CString::from_str_truncate(text);
+    unsafe { SetDlgItemTextW(dialog, control, PCWSTR(wide.as_ptr())) }
+}
+
+fn get_dlg_string(dialog: HWND, control: i32) -> Result<String, windows::core::Error> {
+    get_dlg_osstring(dialog, control).map(|os| os.to_string_lossy().into_owned())
+}
+
+fn get_dlg_check(dialog: HWND, control: i32) -> bool {
+    unsafe { SendDlgItemMessageW(dialog, control, BM_GETCHECK, WPARAM(0), LPARAM(0)).0 != 0 }
+}
+
+fn set_dlg_check(dialog: HWND, control: i32, checked: bool) {
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            control,
+            BM_SETCHECK,
+            WPARAM(checked.into()),
+            LPARAM(0),
+        );
+    }
+}
+
+fn enable_dlg_item(dialog: HWND, control: i32, enable: bool) {
+    if let Ok(item) = unsafe { GetDlgItem(Some(dialog), control) } {
+        let _ = unsafe { EnableWindow(item, enable) };
+    }
+}
+
+/// Replace the drop-down list of a combo box, leaving its text alone.
+fn fill_combo<I>(dialog: HWND, control: i32, items: I)
+where
+    I: IntoIterator<Item = String>,
+{
+    unsafe {
+        let _ = SendDlgItemMessageW(dialog, control, CB_RESETCONTENT, WPARAM(0), LPARAM(0));
+        for item in items {
+            let item = U16CString::from_str_truncate(&item);
+            let _ = SendDlgItemMessageW(
+                dialog,
+                control,
+                CB_ADDSTRING,
+                WPARAM(0),
+                LPARAM(item.as_ptr() as isize),
+            );
+        }
+    }
+}
+
+fn selected_index(dialog: HWND, control: i32) -> Option<usize> {
+    let idx = unsafe { SendDlgItemMessageW(dialog, control, CB_GETCURSEL, WPARAM(0), LPARAM(0)) }.0;
+    usize::try_from(idx).ok()
+}
+
+fn select_index(dialog: HWND, control: i32, idx: usize) {
+    unsafe {
+        let _ = SendDlgItemMessageW(dialog, control, CB_SETCURSEL, WPARAM(idx), LPARAM(0));
+    }
+}
+
+/// Text of the selected drop-down entry. During `CBN_SELCHANGE`, the edit
+/// part of the combo box still has the old text.
+fn selected_combo_text(dialog: HWND, control: i32) -> Option<String> {
+    let idx = unsafe { SendDlgItemMessageW(dialog, control, CB_GETCURSEL, WPARAM(0), LPARAM(0)) }.0;
+    let idx = usize::try_from(idx).ok()?;
+
+    let len =
+        unsafe { SendDlgItemMessageW(dialog, control, CB_GETLBTEXTLEN, WPARAM(idx), LPARAM(0)) }.0;
+    let mut buf: Vec<u16> = vec![0; usize::try_from(len).ok()? + 1];
+    let len = unsafe {
+        SendDlgItemMessageW(
+            dialog,
+            control,
+            CB_GETLBTEXT,
+            WPARAM(idx),
+            LPARAM(buf.as_mut_ptr() as isize),
+        )
+    }
+    .0;
+
+    buf.truncate(usize::try_from(len).ok()?);
+    Some(String::from_utf16_lossy(&buf))
+}
+
+/// Offer recently used files and addresses in their drop-downs.
+fn show_history(dialog: HWND, history: &History) {
+    fill_combo(
+        dialog,
+        IDC_LITEX_KERNEL as i32,
+        history
+            .files
+            .iter()
+            .map(|f| f.to_string_lossy().into_owned()),
+    );
+    fill_combo(
+        dialog,
+        IDC_LITEX_BOOT_ADDR as i32,
+        history.addrs.iter().map(|a| format!("{:#010x}", a)),
+    );
+}
+
+/// Ask for a firmware file, starting where the user last looked.
+fn pick_kernel_file(dialog: HWND) -> Option<PathBuf> {
+    let current = get_dlg_osstring(dialog, IDC_LITEX_KERNEL as i32)
+        .ok()
+        .filter(|f| !f.is_empty())
+        .map(PathBuf::from);
+    let start_dir = TTX_LITEX_STATE.with_borrow(|s| s.history.picker_dir(current.as_deref()));
+
+    let mut picker = FileDialog::new()
+        .add_filter("Firmware images", KERNEL_EXTENSIONS)
+        .add_filter("All files", &["*"]);
+    if let Some(dir) = start_dir {
+        picker = picker.set_directory(dir);
+    }
+
+    let path = picker.pick_file()?;
+    TTX_LITEX_STATE.with_borrow_mut(|s| {
+        s.history.last_dir = path.parent().map(Path::to_path_buf);
+    });
+
+    Some(path)
+}
+
+fn pick_csr_json(dialog: HWND) -> Option<PathBuf> {
+    let current = get_dlg_osstring(dialog, IDC_LITEX_CSR_JSON as i32)
+        .ok()
+        .filter(|f| !f.is_empty())
+        .map(PathBuf::from);
+    let start_dir = current
+        .as_deref()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .or_else(|| TTX_LITEX_STATE.with_borrow(|s| s.history.last_dir.clone()));
+
+    let mut picker = FileDialog::new()
+        .add_filter("LiteX CSR map", &["json"])
+        .add_filter("All files", &["*"]);
+    if let Some(dir) = start_dir {
+        picker = picker.set_directory(dir);
+    }
+
+    picker.pick_file()
+}
+
+/// Load the `csr.json` named in the dialog and offer its memory regions,
+/// keeping `selected` chosen if it's still there. Says what's wrong with the
+/// file under it, if anything.
+fn show_regions(dialog: HWND, selected: Option<&str>) -> Option<CsrMap> {
+    let path = get_dlg_osstring(dialog, IDC_LITEX_CSR_JSON as i32)
+        .ok()
+        .filter(|f| !f.is_empty())
+        .map(PathBuf::from);
+    let map = path.map(|p| CsrMap::load(&p)).transpose();
+    let err = map
+        .as_ref()
+        .err()
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    let _ = set_dlg_str(dialog, IDC_LITEX_CSR_ERR as i32, &err);
+    let map = map.ok().flatten();
+
+    let regions: Vec<_> = map.iter().flat_map(|m| m.load_targets()).cloned().collect();
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_REGION as i32,
+            CB_RESETCONTENT,
+            WPARAM(0),
+            LPARAM(0),
+        );
+    }
+    let any = std::iter::once(ANY_REGION.to_string());
+    for entry in any.chain(regions.iter().map(|r| r.to_string())) {
+        let entry = U16CString::from_str_truncate(&entry);
+        unsafe {
+            let _ = SendDlgItemMessageW(
+                dialog,
+                IDC_LITEX_REGION as i32,
+                CB_ADDSTRING,
+                WPARAM(0),
+                LPARAM(entry.as_ptr() as isize),
+            );
+        }
+    }
+
+    let idx = selected
+        .and_then(|name| regions.iter().position(|r| r.name == name))
+        .map_or(0, |i| i + 1);
+    select_index(dialog, IDC_LITEX_REGION as i32, idx);
+    enable_dlg_item(dialog, IDC_LITEX_REGION as i32, !regions.is_empty());
+    REGIONS.set(regions.into_iter().map(|r| r.name).collect());
+
+    map
+}
+
+/// Symbols that addresses in the dialog can use: those from the `csr.json`
+/// it names, then the board's own.
+fn dlg_symbols(dialog: HWND) -> Symbols {
+    let mut symbols = get_dlg_osstring(dialog, IDC_LITEX_CSR_JSON as i32)
+        .ok()
+        .filter(|f| !f.is_empty())
+        .and_then(|f| CsrMap::load(Path::new(&f)).ok())
+        .map(|map| map.symbols())
+        .unwrap_or_default();
+    BOARD_SYMBOLS.with_borrow(|table| {
+        for (name, value) in table {
+            symbols.insert(name, *value as u64);
+        }
+    });
+
+    symbols
+}
+
+/// Name of the memory region chosen in the dialog, if any.
+fn selected_region(dialog: HWND) -> Option<String> {
+    let idx = selected_index(dialog, IDC_LITEX_REGION as i32)?.checked_sub(1)?;
+    REGIONS.with_borrow(|r| r.get(idx).cloned())
+}
+
+/// Show a protocol profile's settings. Only the custom profile is editable,
+/// and only the user's named profiles can be deleted.
+fn show_protocol(dialog: HWND, protocol: &Profile) {
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_MAGIC as i32,
+        &profile::escape(&protocol.magic),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_MAGIC_RESPONSE as i32,
+        &profile::escape(&protocol.response),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_MAX_PAYLOAD as i32,
+        &protocol.max_payload.to_string(),
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_ALLOW_JUMP as i32,
+        protocol.allows(Cmd::Jump),
+    );
+
+    for control in [
+        IDC_LITEX_MAGIC,
+        IDC_LITEX_MAGIC_RESPONSE,
+        IDC_LITEX_MAX_PAYLOAD,
+        IDC_LITEX_ALLOW_JUMP,
+    ] {
+        enable_dlg_item(dialog, control as i32, protocol.is_custom());
+    }
+    enable_dlg_item(
+        dialog,
+        IDC_LITEX_PROTOCOL_DELETE as i32,
+        !protocol.is_builtin(),
+    );
+}
+
+/// Refill the protocol combo box from [`Profile::all`].
+fn fill_protocol_combo(dialog: HWND) {
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_PROTOCOL as i32,
+            CB_RESETCONTENT,
+            WPARAM(0),
+            LPARAM(0),
+        );
+    }
+
+    for p in PROTOCOLS.with_borrow(|user| Profile::all(user)) {
+        let name = U16CString::from_str_truncate(&p.name);
+        unsafe {
+            let _ = SendDlgItemMessageW(
+                dialog,
+                IDC_LITEX_PROTOCOL as i32,
+                CB_ADDSTRING,
+                WPARAM(0),
+                LPARAM(name.as_ptr() as isize),
+            );
+        }
+    }
+}
+
+fn selected_protocol_name(dialog: HWND) -> Option<String> {
+    let idx = unsafe {
+        SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_PROTOCOL as i32,
+            CB_GETCURSEL,
+            WPARAM(0),
+            LPARAM(0),
+        )
+    }
+    .0;
+
+    usize::try_from(idx)
+        .ok()
+        .and_then(|i| PROTOCOLS.with_borrow(|user| Profile::all(user).into_iter().nth(i)))
+        .map(|p| p.name)
+}
+
+/// Read back the selected protocol profile. Named profiles are taken
+/// as-is; the custom profile is assembled from the edit controls.
+fn get_dlg_protocol(dialog: HWND) -> Result<Profile, String> {
+    let name = selected_protocol_name(dialog).ok_or("no protocol selected")?;
+    let mut protocol = PROTOCOLS
+        .with_borrow(|user| Profile::find(&name, user))
+        .ok_or("unknown protocol")?;
+
+    if !protocol.is_custom() {
+        return Ok(protocol);
+    }
+
+    let magic = get_dlg_string(dialog, IDC_LITEX_MAGIC as i32)
+        .ok()
+        .and_then(|m| profile::unescape(&m))
+        .filter(|m| !m.is_empty())
+        .ok_or("magic string is empty or has a bad escape")?;
+    let response = get_dlg_string(dialog, IDC_LITEX_MAGIC_RESPONSE as i32)
+        .ok()
+        .and_then(|r| profile::unescape(&r))
+        .ok_or("response string has a bad escape")?;
+    let max_payload = get_dlg_string(dialog, IDC_LITEX_MAX_PAYLOAD as i32)
+        .ok()
+        .and_then(|p| parse::<u8>(&p).ok())
+        .filter(|p| (MIN_PAYLOAD..=MAX_PAYLOAD).contains(p))
+        .ok_or("max payload must be between 5 and 255")?;
+
+    protocol.magic = magic;
+    protocol.response = response;
+    protocol.max_payload = max_payload;
+    if !get_dlg_check(dialog, IDC_LITEX_ALLOW_JUMP as i32) {
+        protocol.commands.retain(|c| *c != Cmd::Jump);
+    }
+
+    Ok(protocol)
+}
+
+/// Show the advanced transfer options. "0" stands for "no limit".
+fn show_options(dialog: HWND, options: &TransferOptions) {
+    set_dlg_check(dialog, IDC_LITEX_CHUNK_AUTO as i32, options.calibrates());
+    let chunk = options
+        .chunk_size
+        .map(|c| c.to_string())
+        .unwrap_or_default();
+    let _ = set_dlg_str(dialog, IDC_LITEX_CHUNK_SIZE as i32, &chunk);
+    enable_dlg_item(dialog, IDC_LITEX_CHUNK_SIZE as i32, !options.calibrates());
+
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_MAX_IN_FLIGHT as i32,
+        &options.max_in_flight.to_string(),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_FRAME_TIMEOUT as i32,
+        &options
+            .frame_timeout
+            .map_or(0, |t| t.as_millis())
+            .to_string(),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_MAX_RETRIES as i32,
+        &options.max_retries.unwrap_or(0).to_string(),
+    );
+    set_dlg_check(dialog, IDC_LITEX_STATUS_BAR as i32, options.status_bar);
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_STATUS_COMPACT as i32,
+        options.status_style == StatusStyle::Compact,
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_PROGRESS_WINDOW as i32,
+        options.progress_window,
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_DETAILED_REPORT as i32,
+        options.detailed_report,
+    );
+
+    fill_combo(
+        dialog,
+        IDC_LITEX_MESSAGE_LEVEL as i32,
+        MessageLevel::ALL.iter().map(|l| format!("{:?}", l)),
+    );
+    let level = MessageLevel::ALL
+        .iter()
+        .position(|l| *l == options.message_level)
+        .unwrap_or(0);
+    select_index(dialog, IDC_LITEX_MESSAGE_LEVEL as i32, level);
+
+    // "None" comes first, then the colors.
+    fill_combo(
+        dialog,
+        IDC_LITEX_MESSAGE_COLOR as i32,
+        std::iter::once("None".to_string()).chain(Color::ALL.iter().map(|c| format!("{:?}", c))),
+    );
+    let color = options
+        .message_color
+        .and_then(|c| Color::ALL.iter().position(|a| *a == c))
+        .map_or(0, |i| i + 1);
+    select_index(dialog, IDC_LITEX_MESSAGE_COLOR as i32, color);
+
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_DETECT_BOOT_ADDR as i32,
+        options.detect_boot_addr,
+    );
+
+    fill_combo(
+        dialog,
+        IDC_LITEX_TRIGGER as i32,
+        TRIGGERS.iter().map(|t| profile::escape(t)),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_TRIGGER as i32,
+        &profile::escape(&options.trigger),
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_TRIGGER_TIMEOUT as i32,
+        &options
+            .trigger_timeout
+            .map_or(0, |t| t.as_millis())
+            .to_string(),
+    );
+    set_dlg_check(dialog, IDC_LITEX_WATCH_FILE as i32, options.watch_file);
+
+    fill_combo(
+        dialog,
+        IDC_LITEX_ARM_MODE as i32,
+        ARM_MODES.iter().map(|m| m.to_string()),
+    );
+    let (mode, count) = match options.arm_mode {
+        ArmMode::Always => (0, 2),
+        ArmMode::Once => (1, 2),
+        ArmMode::Times(n) => (2, n),
+    };
+    select_index(dialog, IDC_LITEX_ARM_MODE as i32, mode);
+    let _ = set_dlg_str(dialog, IDC_LITEX_ARM_COUNT as i32, &count.to_string());
+    enable_dlg_item(dialog, IDC_LITEX_ARM_COUNT as i32, mode == 2);
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_ARM_EXPIRY as i32,
+        &options
+            .arm_expiry
+            .map_or(0, |t| t.as_secs() / 60)
+            .to_string(),
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_CONFIRM_UPLOAD as i32,
+        options.confirm_upload,
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_RESTART_ON_RESET as i32,
+        options.restart_on_reset,
+    );
+    set_dlg_check(
+        dialog,
+        IDC_LITEX_RESUME_UPLOADS as i32,
+        options.resume_uploads,
+    );
+    let _ = set_dlg_str(
+        dialog,
+        IDC_LITEX_BOOT_PATTERN as i32,
+        &profile::escape(&options.boot_pattern),
+    );
+}
+
+fn get_dlg_options(dialog: HWND) -> Result<TransferOptions, (usize, String)> {
+    let number = |control: usize, range: RangeInclusive<u32>, what: &str| {
+        get_dlg_string(dialog, control as i32)
+            .ok()
+            .and_then(|n| parse::<u32>(n.trim()).ok())
+            .filter(|n| range.contains(n))
+            .ok_or_else(|| {
+                let msg = format!(
+                    "{} must be between {} and {}.",
+                    what,
+                    range.start(),
+                    range.end()
+                );
+                (control, msg)
+            })
+    };
+
+    let chunk_size = if get_dlg_check(dialog, IDC_LITEX_CHUNK_AUTO as i32) {
+        None
+    } else {
+        Some(number(
+            IDC_LITEX_CHUNK_SIZE,
+            1..=MAX_CHUNK_SIZE as u32,
+            "Chunk size",
+        )? as u16)
+    };
+    let max_in_flight = number(
+        IDC_LITEX_MAX_IN_FLIGHT,
+        1..=MAX_IN_FLIGHT,
+        "Frames in flight",
+    )?;
+    let timeout = number(IDC_LITEX_FRAME_TIMEOUT, 0..=600_000, "Timeout")?;
+    // Acks carry no frame number, so a late one after a resend would be
+    // taken for a later frame's.
+    if timeout != 0 && max_in_flight > 1 {
+        let msg = "A timeout needs 1 frame in flight.".to_string();
+        return Err((IDC_LITEX_FRAME_TIMEOUT, msg));
+    }
+    let retries = number(IDC_LITEX_MAX_RETRIES, 0..=1000, "Retries")?;
+    let trigger = get_dlg_string(dialog, IDC_LITEX_TRIGGER as i32)
+        .ok()
+        .and_then(|t| profile::unescape(&t))
+        .ok_or_else(|| {
+            let msg = "Trigger has a bad escape sequence.".to_string();
+            (IDC_LITEX_TRIGGER, msg)
+        })?;
+    let trigger_timeout = number(IDC_LITEX_TRIGGER_TIMEOUT, 0..=600_000, "Trigger timeout")?;
+    let arm_mode = match selected_index(dialog, IDC_LITEX_ARM_MODE as i32) {
+        Some(1) => ArmMode::Once,
+        Some(2) => ArmMode::Times(number(IDC_LITEX_ARM_COUNT, 2..=1000, "Uploads")?),
+        _ => ArmMode::Always,
+    };
+    let arm_expiry = number(IDC_LITEX_ARM_EXPIRY, 0..=10_080, "Disarm after")?;
+    let confirm_upload = get_dlg_check(dialog, IDC_LITEX_CONFIRM_UPLOAD as i32);
+    // Agreeing sends the trigger, so the receiver asks again.
+    if confirm_upload && trigger.is_empty() {
+        let msg = "Asking before uploading needs a trigger.".to_string();
+        return Err((IDC_LITEX_TRIGGER, msg));
+    }
+    let boot_pattern = get_dlg_string(dialog, IDC_LITEX_BOOT_PATTERN as i32)
+        .ok()
+        .and_then(|p| profile::unescape(&p))
+        .ok_or_else(|| {
+            let msg = "Boot pattern has a bad escape sequence.".to_string();
+            (IDC_LITEX_BOOT_PATTERN, msg)
+        })?;
+
+    Ok(TransferOptions {
+        chunk_size,
+        max_in_flight,
+        frame_timeout: (timeout != 0).then(|| Duration::from_millis(timeout.into())),
+        max_retries: (retries != 0).then_some(retries),
+        status_bar: get_dlg_check(dialog, IDC_LITEX_STATUS_BAR as i32),
+        status_style: if get_dlg_check(dialog, IDC_LITEX_STATUS_COMPACT as i32) {
+            StatusStyle::Compact
+        } else {
+            StatusStyle::Full
+        },
+        progress_window: get_dlg_check(dialog, IDC_LITEX_PROGRESS_WINDOW as i32),
+        detailed_report: get_dlg_check(dialog, IDC_LITEX_DETAILED_REPORT as i32),
+        message_level: selected_index(dialog, IDC_LITEX_MESSAGE_LEVEL as i32)
+            .and_then(|i| MessageLevel::ALL.get(i).copied())
+            .unwrap_or(MessageLevel::Normal),
+        message_color: selected_index(dialog, IDC_LITEX_MESSAGE_COLOR as i32)
+            .and_then(|i| i.checked_sub(1))
+            .and_then(|i| Color::ALL.get(i).copied()),
+        detect_boot_addr: get_dlg_check(dialog, IDC_LITEX_DETECT_BOOT_ADDR as i32),
+        trigger,
+        trigger_timeout: (trigger_timeout != 0)
+            .then(|| Duration::from_millis(trigger_timeout.into())),
+        watch_file: get_dlg_check(dialog, IDC_LITEX_WATCH_FILE as i32),
+        arm_mode,
+        confirm_upload,
+        restart_on_reset: get_dlg_check(dialog, IDC_LITEX_RESTART_ON_RESET as i32),
+        resume_uploads: get_dlg_check(dialog, IDC_LITEX_RESUME_UPLOADS as i32),
+        boot_pattern,
+        arm_expiry: (arm_expiry != 0).then(|| Duration::from_secs(u64::from(arm_expiry) * 60)),
+    })
+}
+
+/// Everything the user typed into the setup dialog, before validation.
+struct DlgSettings {
+    kernel_path: Option<PathBuf>,
+    boot_addr: Result<u32, Error>,
+    jump_addr: Result<Option<u32>, Error>,
+    csr_json: Option<PathBuf>,
+    region: Option<String>,
+    protocol: Result<Profile, String>,
+    /// On error, also says which control is wrong.
+    options: Result<TransferOptions, (usize, String)>,
+}
+
+impl DlgSettings {
+    fn read(dialog: HWND) -> Self {
+        let kernel_path = get_dlg_osstring(dialog, IDC_LITEX_KERNEL as i32)
+            .inspect_err(|e| error!(target: "setup_dialog", "Could not read kernel path: {}", e))
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+
+        let symbols = dlg_symbols(dialog);
+        let boot_addr = get_dlg_string(dialog, IDC_LITEX_BOOT_ADDR as i32)
+            .map_err(Error::WinError)
+            .and_then(|b| expr::parse_address(&b, &symbols));
+
+        // Blank means "jump to the boot address".
+        let jump_addr = get_dlg_string(dialog, IDC_LITEX_JUMP_ADDR as i32)
+            .ok()
+            .filter(|j| !j.trim().is_empty())
+            .map(|j| expr::parse_address(&j, &symbols))
+            .transpose();
+
+        let csr_json = get_dlg_osstring(dialog, IDC_LITEX_CSR_JSON as i32)
+            .ok()
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from);
+        let region = selected_region(dialog);
+
+        let protocol = get_dlg_protocol(dialog);
+        let options = get_dlg_options(dialog);
+
+        debug!(target: "setup_dialog", "Kernel Path: {:?}", kernel_path);
+        debug!(target: "setup_dialog", "Boot Address: {:?}", boot_addr);
+        debug!(target: "setup_dialog", "Jump Address: {:?}", jump_addr);
+        debug!(target: "setup_dialog", "CSR JSON: {:?}", csr_json);
+        debug!(target: "setup_dialog", "Region: {:?}", region);
+        debug!(target: "setup_dialog", "Protocol: {:?}", protocol);
+        debug!(target: "setup_dialog", "Options: {:?}", options);
+
+        Self {
+            kernel_path,
+            boot_addr,
+            jump_addr,
+            csr_json,
+            region,
+            protocol,
+            options,
+        }
+    }
+
+    /// Check the settings before the dialog is allowed to close. The file
+    /// only has to exist if an upload is being armed, so that settings for
+    /// firmware that hasn't been built yet can still be saved.
+    fn validate(&self, active: bool) -> Problems {
+        let mut problems = Problems::default();
+
+        let file_len = match &self.kernel_path {
+            None if active => {
+                problems.kernel = Some("Choose a file to upload.".to_string());
+                None
+            }
+            Some(path) if active => match check_kernel_file(path) {
+                Ok(len) => Some(len),
+                Err(msg) => {
+                    problems.kernel = Some(msg);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        // Like the file, csr.json may not have been built yet.
+        let csr_map = match &self.csr_json {
+            Some(path) if active => match CsrMap::load(path) {
+                Ok(map) => Some(map),
+                Err(e) => {
+                    problems.csr = Some(e.to_string());
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        match &self.boot_addr {
+            Err(e) => problems.boot_addr = Some(address_problem(e)),
+            Ok(addr) if addr % ADDR_ALIGN != 0 => {
+                problems.boot_addr = Some(format!("Must be a multiple of {}.", ADDR_ALIGN));
+            }
+            Ok(addr) => {
+                if let Some(len) = file_len.filter(|len| *addr as u64 + len > 1 << 32) {
+                    problems.boot_addr = Some(format!(
+                        "The file ({} bytes) runs past 0xffffffff at this address.",
+                        len
+                    ));
+                } else if let (Some(map), Some(len)) = (&csr_map, file_len) {
+                    if let Err(msg) = map.check(self.region.as_deref(), *addr, len) {
+                        problems.boot_addr = Some(sentence(&msg));
+                    }
+                }
+            }
+        }
+
+        match &self.jump_addr {
+            Err(e) => problems.jump_addr = Some(address_problem(e)),
+            Ok(Some(addr)) if addr % ADDR_ALIGN != 0 => {
+                problems.jump_addr = Some(format!("Must be a multiple of {}.", ADDR_ALIGN));
+            }
+            Ok(_) => {}
+        }
+
+        if let Err(e) = &self.protocol {
+            problems.protocol = Some(sentence(e));
+        }
+
+        if let Err((control, msg)) = &self.options {
+            problems.advanced = Some((*control, msg.clone()));
+        }
+
+        problems
+    }
+
+    /// Settings as a board profile. Fields that didn't parse fall back to
+    /// their defaults.
+    fn to_board(&self, name: &str) -> Board {
+        let mut board = Board::new(name);
+
+        board.filename = self.kernel_path.clone();
+        if let Ok(addr) = self.boot_addr {
+            board.addr = addr;
+        }
+        board.jump_addr = self.jump_addr.as_ref().ok().copied().flatten();
+        board.csr_json = self.csr_json.clone();
+        board.region = self.region.clone();
+        board.symbols = BOARD_SYMBOLS.with_borrow(Clone::clone);
+        if let Ok(p) = &self.protocol {
+            board.protocol = p.clone();
+        }
+
+        board
+    }
+}
+
+/// Turn an error message fragment into a sentence for an error label.
+fn sentence(msg: &str) -> String {
+    let mut msg = msg.to_string();
+    if let Some(first) = msg.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    msg.push('.');
+    msg
+}
+
+fn address_problem(e: &Error) -> String {
+    match e {
+        Error::BadAddressError { why, .. } => sentence(why),
+        e => sentence(&e.to_string()),
+    }
+}
+
+/// Describe what would be uploaded with the settings in the dialog. `file`
+/// overrides the "File" field, whose text lags behind during
+/// `CBN_SELCHANGE`.
+fn show_summary(dialog: HWND, file: Option<&Path>) {
+    let settings = DlgSettings::read(dialog);
+
+    // Show what address expressions work out to.
+    let mut resolved = String::new();
+    for (control, name, addr) in [
+        (
+            IDC_LITEX_BOOT_ADDR,
+            "Boot Address",
+            settings.boot_addr.as_ref().ok().copied(),
+        ),
+        (
+            IDC_LITEX_JUMP_ADDR,
+            "Jump Address",
+            settings.jump_addr.as_ref().ok().copied().flatten(),
+        ),
+    ] {
+        let text = get_dlg_string(dialog, control as i32).unwrap_or_default();
+        if let Some(addr) = addr.filter(|_| !expr::is_literal(&text)) {
+            let _ = write!(resolved, "{}: {} = {:#010x}\r\n", name, text.trim(), addr);
+        }
+    }
+
+    // Offer what the BIOS said, if it disagrees.
+    if let Some(info) = TTX_LITEX_STATE.with_borrow(|s| s.bios.clone()) {
+        let differs = |addr: &u32| settings.boot_addr.as_ref().ok() != Some(addr);
+        if let Some(addr) = info.memtest.filter(differs) {
+            let _ = write!(resolved, "{} has main RAM at {:#010x}\r\n", info, addr);
+        }
+    }
+
+    let Some(path) = file.map(Path::to_path_buf).or(settings.kernel_path) else {
+        let _ = set_dlg_str(dialog, IDC_LITEX_SUMMARY as i32, resolved.trim_end());
+        return;
+    };
+
+    let config = ProjectConfig::discover(&path).ok().flatten();
+    let addr = config
+        .as_ref()
+        .and_then(|c| c.load_address)
+        .or(settings.boot_addr.ok())
+        .unwrap_or(DEFAULT_ADDR);
+    let mut protocol = settings.protocol.unwrap_or_default();
+    if let Some(p) = config.as_ref().and_then(|c| c.protocol(&protocol).ok()) {
+        protocol = p;
+    }
+
+    let chunk_size = settings
+        .options
+        .unwrap_or_default()
+        .initial_chunk_size(protocol.max_chunk_size());
+
+    let text = match Summary::new(&path, addr, config.as_ref(), &protocol, chunk_size) {
+        Ok(summary) => {
+            let line = TTX_LITEX_STATE.with_borrow(Line::current);
+            let mut text = summary.describe(line.as_ref());
+            if let Some(map) = settings.csr_json.and_then(|p| CsrMap::load(&p).ok()) {
+                let images = summary.segments.iter().map(|seg| (seg.base, seg.len));
+                match map.check_images(settings.region.as_deref(), images) {
+                    Ok(warnings) => {
+                        for warning in warnings {
+                            let _ = write!(text, "\r\nWarning: {}", sentence(&warning));
+                        }
+                    }
+                    Err(msg) => {
+                        let _ = write!(text, "\r\nWon't fit: {}", sentence(&msg));
+                    }
+                }
+            }
+            text
+        }
+        Err(e) => format!("Cannot read {}: {}", path.display(), e),
+    };
+    let _ = set_dlg_str(dialog, IDC_LITEX_SUMMARY as i32, &(resolved + &text));
+}
+
+/// How often the dialog refreshes the transfer status.
+const STATUS_TIMER_ID: usize = 1;
+const STATUS_INTERVAL_MS: u32 = 250;
+
+/// Show how far along the current upload is, if one is underway.
+fn show_transfer_status(dialog: HWND) {
+    let text = match TTX_LITEX_STATE.with_borrow_mut(Progress::current) {
+        None => String::new(),
+        Some(p) if p.activity == Activity::Calibrate => {
+            format!("Calibrating chunk size ({} bytes)...", p.chunk_size)
+        }
+        Some(p) if p.activity == Activity::WaitFinalResp => {
+            "Upload complete, waiting for the receiver to jump.".to_string()
+        }
+        Some(p) => {
+            let secs = p.elapsed.as_secs();
+            format!(
+                "Uploading: {:.0}% ({} of {}, {} retries, {}:{:02})",
+                p.fraction() * 100.0,
+                pretty_bytes(p.bytes_acked(), Some(1)),
+                pretty_bytes(p.total_bytes, Some(1)),
+                p.retries,
+                secs / 60,
+                secs % 60
+            )
+        }
+    };
+    let _ = set_dlg_str(dialog, IDC_LITEX_TRANSFER_STATUS as i32, &text);
+}
+
+/// Addresses must be word-aligned.
+const ADDR_ALIGN: u32 = 4;
+
+/// Make sure a file can be uploaded, and return its length.
+fn check_kernel_file(path: &Path) -> Result<u64, String> {
+    let meta = fs::metadata(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => "File does not exist.".to_string(),
+        _ => format!("Cannot read file: {}", e),
+    })?;
+
+    if !meta.is_file() {
+        return Err("Not a file.".to_string());
+    }
+    if meta.len() == 0 {
+        return Err("File is empty.".to_string());
+    }
+    File::open(path).map_err(|e| format!("Cannot read file: {}", e))?;
+
+    Ok(meta.len())
+}
+
+/// Problems found by [`DlgSettings::validate`], each shown in red under the
+/// field it's about.
+#[derive(Default, Debug)]
+struct Problems {
+    kernel: Option<String>,
+    boot_addr: Option<String>,
+    jump_addr: Option<String>,
+    csr: Option<String>,
+    protocol: Option<String>,
+    /// Which advanced option is wrong, and why.
+    advanced: Option<(usize, String)>,
+}
+
+impl Problems {
+    fn is_empty(&self) -> bool {
+        self.kernel.is_none()
+            && self.boot_addr.is_none()
+            && self.jump_addr.is_none()
+            && self.csr.is_none()
+            && self.protocol.is_none()
+            && self.advanced.is_none()
+    }
+
+    /// Show each problem next to its field, clearing any fixed since last
+    /// time, and move focus to the first bad field.
+    fn show(&self, dialog: HWND) {
+        let fields = [
+            (&self.kernel, IDC_LITEX_KERNEL_ERR, IDC_LITEX_KERNEL),
+            (
+                &self.boot_addr,
+                IDC_LITEX_BOOT_ADDR_ERR,
+                IDC_LITEX_BOOT_ADDR,
+            ),
+            (
+                &self.jump_addr,
+                IDC_LITEX_JUMP_ADDR_ERR,
+                IDC_LITEX_JUMP_ADDR,
+            ),
+            (&self.csr, IDC_LITEX_CSR_ERR, IDC_LITEX_CSR_JSON),
+            (&self.protocol, IDC_LITEX_PROTOCOL_ERR, IDC_LITEX_PROTOCOL),
+            (
+                &self.advanced.as_ref().map(|(_, msg)| msg.clone()),
+                IDC_LITEX_ADVANCED_ERR,
+                self.advanced
+                    .as_ref()
+                    .map_or(IDC_LITEX_CHUNK_SIZE, |(control, _)| *control),
+            ),
+        ];
+
+        for (problem, label, _) in fields {
+            let _ = set_dlg_str(dialog, label as i32, problem.as_deref().unwrap_or_default());
+        }
+
+        if let Some((_, _, field)) = fields.iter().find(|(p, _, _)| p.is_some()) {
+            if let Ok(item) = unsafe { GetDlgItem(Some(dialog), *field as i32) } {
+                let _ = unsafe { SetFocus(Some(item)) };
+            }
+        }
+    }
+}
+
+/// Error labels, so that `WM_CTLCOLORSTATIC` can draw them in red.
+const ERROR_LABELS: [usize; 6] = [
+    IDC_LITEX_KERNEL_ERR,
+    IDC_LITEX_BOOT_ADDR_ERR,
+    IDC_LITEX_JUMP_ADDR_ERR,
+    IDC_LITEX_CSR_ERR,
+    IDC_LITEX_PROTOCOL_ERR,
+    IDC_LITEX_ADVANCED_ERR,
+];
+
+/// Show a board's upload settings in the setup dialog.
+fn show_board(dialog: HWND, board: &Board) {
+    let file = board
+        .filename
+        .as_ref()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let _ = set_dlg_str(dialog, IDC_LITEX_KERNEL as i32, &file);
+
+    let mut addr_str = String::new();
+    let _ = write!(&mut addr_str, "{:#08x}", board.addr);
+    let _ = set_dlg_str(dialog, IDC_LITEX_BOOT_ADDR as i32, &addr_str);
+
+    let jump_str = board
+        .jump_addr
+        .map(|a| format!("{:#08x}", a))
+        .unwrap_or_default();
+    let _ = set_dlg_str(dialog, IDC_LITEX_JUMP_ADDR as i32, &jump_str);
+
+    let csr_json = board
+        .csr_json
+        .as_ref()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let _ = set_dlg_str(dialog, IDC_LITEX_CSR_JSON as i32, &csr_json);
+    show_regions(dialog, board.region.as_deref());
+    BOARD_SYMBOLS.set(board.symbols.clone());
+
+    select_protocol(dialog, &board.protocol);
+}
+
+/// Select a protocol profile in the drop-down and show its settings.
+fn select_protocol(dialog: HWND, protocol: &Profile) {
+    if let Some(i) = PROTOCOLS.with_borrow(|user| {
+        Profile::all(user)
+            .iter()
+            .position(|p| p.name == protocol.name)
+    }) {
+        unsafe {
+            let _ = SendDlgItemMessageW(
+                dialog,
+                IDC_LITEX_PROTOCOL as i32,
+                CB_SETCURSEL,
+                WPARAM(i),
+                LPARAM(0),
+            );
+        }
+    }
+    show_protocol(dialog, protocol);
+}
+
+/// Find the project config for `firmware` and say which one will be used.
+fn show_project_config(dialog: HWND, firmware: &Path) -> Option<ProjectConfig> {
+    let (text, config) = match ProjectConfig::discover(firmware) {
+        Ok(Some(config)) => (
+            format!(
+                "Using {} ({} extra images)",
+                config.path.display(),
+                config.images.len()
+            ),
+            Some(config),
+        ),
+        Ok(None) => (String::new(), None),
+        Err(e) => {
+            error!(target: "setup_dialog", "{}", e);
+            (e.to_string(), None)
+        }
+    };
+
+    let _ = set_dlg_str(dialog, IDC_LITEX_PROJECT_CONFIG as i32, &text);
+    config
+}
+
+/// Fill in the settings a project config gives. Anything it doesn't mention
+/// is left as the user typed it.
+fn apply_project_config(dialog: HWND, config: &ProjectConfig) {
+    if let Some(addr) = config.load_address {
+        let _ = set_dlg_str(
+            dialog,
+            IDC_LITEX_BOOT_ADDR as i32,
+            &format!("{:#08x}", addr),
+        );
+    }
+    if let Some(jump_addr) = config.jump_address {
+        let _ = set_dlg_str(
+            dialog,
+            IDC_LITEX_JUMP_ADDR as i32,
+            &format!("{:#08x}", jump_addr),
+        );
+    }
+
+    let current = get_dlg_protocol(dialog).unwrap_or_default();
+    if let Ok(protocol) = config.protocol(&current) {
+        select_protocol(dialog, &protocol);
+    }
+}
+
+/// Board profiles as edited in the setup dialog. Only committed to
+/// [`State`](super::state::State) on OK.
+#[derive(Default)]
+struct BoardEdit {
+    boards: Vec<Board>,
+    port_boards: HashMap<u16, String>,
+    selected: Option<usize>,
+}
+
+thread_local! {
+    static BOARD_EDIT: RefCell<BoardEdit> = RefCell::new(BoardEdit::default());
+    static BOARD_NAME: RefCell<String> = const { RefCell::new(String::new()) };
+    /// The user's named protocol profiles as edited in the setup dialog.
+    /// Only committed to [`State`](super::state::State) on OK.
+    static PROTOCOLS: RefCell<Vec<Profile>> = const { RefCell::new(Vec::new()) };
+    /// Names of the regions in the region combo box, after [`ANY_REGION`].
+    static REGIONS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// Symbol table of the board being edited. The dialog has no field for
+    /// it, so it's carried along from [`show_board`].
+    static BOARD_SYMBOLS: RefCell<BTreeMap<String, u32>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Refill the board combo box. Entry 0 is always "no board".
+fn fill_board_combo(dialog: HWND, edit: &BoardEdit) {
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_BOARD as i32,
+            CB_RESETCONTENT,
+            WPARAM(0),
+            LPARAM(0),
+        );
+    }
+
+    let none = std::iter::once(NO_BOARD.to_string());
+    for name in none.chain(edit.boards.iter().map(|b| b.name.clone())) {
+        let name = U16CString::from_str_truncate(&name);
+        unsafe {
+            let _ = SendDlgItemMessageW(
+                dialog,
+                IDC_LITEX_BOARD as i32,
+                CB_ADDSTRING,
+                WPARAM(0),
+                LPARAM(name.as_ptr() as isize),
+            );
+        }
+    }
+
+    unsafe {
+        let _ = SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_BOARD as i32,
+            CB_SETCURSEL,
+            WPARAM(edit.selected.map(|i| i + 1).unwrap_or(0)),
+            LPARAM(0),
+        );
+    }
+
+    enable_dlg_item(
+        dialog,
+        IDC_LITEX_BOARD_RENAME as i32,
+        edit.selected.is_some(),
+    );
+    enable_dlg_item(
+        dialog,
+        IDC_LITEX_BOARD_DELETE as i32,
+        edit.selected.is_some(),
+    );
+}
+
+fn selected_board(dialog: HWND) -> Option<usize> {
+    let idx = unsafe {
+        SendDlgItemMessageW(
+            dialog,
+            IDC_LITEX_BOARD as i32,
+            CB_GETCURSEL,
+            WPARAM(0),
+            LPARAM(0),
+        )
+    }
+    .0;
+
+    usize::try_from(idx).ok().and_then(|i| i.checked_sub(1))
+}
+
+/// Ask for a board or protocol name, starting with `initial`, in a box
+/// captioned `title`. Returns `None` on Cancel.
+fn prompt_name(dialog: HWND, title: &U16CStr, initial: &str) -> Option<String> {
+    BOARD_NAME.set(initial.to_string());
+
+    let res = unsafe {
+        DialogBoxParamW(
+            Some(OUR_HINST.get()),
+            PCWSTR(IDD_LITEX_BOARD_NAME as *const u16),
+            Some(dialog),
+            Some(Some(board_name_dialog)),
+            LPARAM(title.as_ptr() as isize),
+        )
+    };
+
+    (res == IDOK.0 as isize).then(|| BOARD_NAME.take())
+}
+
+/// Ask whether to abort the running upload so new settings can be applied.
+fn confirm_abort(dialog: HWND) -> bool {
+    let answer = unsafe {
+        MessageBoxW(
+            Some(dialog),
+            PCWSTR(
+                u16cstr!("An upload is in progress. Abort it and apply the new settings?").as_ptr(),
+            ),
+            PCWSTR(u16cstr!("LiteX Setup").as_ptr()),
+            MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2,
+        )
+    };
+    answer == IDYES
+}
+
+/// Ask whether to answer the receiver's request for an upload.
+pub fn confirm_upload(window: HWND, question: &str) -> bool {
+    let question = U16CString::from_str_truncate(question);
+    let answer = unsafe {
+        MessageBoxW(
+            Some(window),
+            PCWSTR(question.as_ptr()),
+            PCWSTR(u16cstr!("LiteX Upload").as_ptr()),
+            MB_YESNO | MB_ICONQUESTION | MB_DEFBUTTON2,
+        )
+    };
+    answer == IDYES
+}
+
+/// Board names must be non-empty and unique (other than `except`, the
+/// board being renamed).
+fn check_board_name(
+    dialog: HWND,
+    edit: &BoardEdit,
+    name: &str,
+    except: Option<usize>,
+) -> Option<String> {
+    let name = name.trim();
+    let msg = if name.is_empty() {
+        "Board name can't be empty."
+    } else if name == NO_BOARD {
+        "That board name is reserved."
+    } else if edit
+        .boards
+        .iter()
+        .enumerate()
+        .any(|(i, b)| b.name == name && Some(i) != except)
+    {
+        "A board with that name already exists."
+    } else {
+        return Some(name.to_string());
+    };
+
+    warn_name(dialog, msg);
+    None
+}
+
+/// Protocol names must be non-empty and not taken by a built-in profile.
+/// Taking a named profile's name replaces it.
+fn check_protocol_name(dialog: HWND, name: &str) -> Option<String> {
+    let name = name.trim();
+    let msg = if name.is_empty() {
+        "Protocol name can't be empty."
+    } else if Profile::by_name(name).is_some() {
+        "That protocol name is reserved."
+    } else {
+        return Some(name.to_string());
+    };
+
+    warn_name(dialog, msg);
+    None
+}
+
+fn warn_name(dialog: HWND, msg: &str) {
+    let msg = U16CString::from_str_truncate(msg);
+    unsafe {
+        MessageBoxW(
+            Some(dialog),
+            PCWSTR(msg.as_ptr()),
+            PCWSTR(u16cstr!("LiteX Setup").as_ptr()),
+            MB_OK | MB_ICONWARNING,
+        );
+    }
+}
+
+unsafe extern "system" fn board_name_dialog(
+    dialog: HWND,
+    msg: u32,
+    param_1: WPARAM,
+    param_2: LPARAM,
+) -> isize {
+    match msg {
+        WM_INITDIALOG => {
+            // The title passed to DialogBoxParamW by prompt_name.
+            let _ = SetWindowTextW(dialog, PCWSTR(param_2.0 as *const u16));
+            let _ = set_dlg_str(dialog, IDC_LITEX_BOARD_NAME as i32, &BOARD_NAME.take());
+            return true.into();
+        }
+        WM_COMMAND => match param_1.0 as i32 {
+            p if p == IDOK.0 => {
+                BOARD_NAME
+                    .set(get_dlg_string(dialog, IDC_LITEX_BOARD_NAME as i32).unwrap_or_default());
+                let _ = EndDialog(dialog, IDOK.0 as isize);
+                return true.into();
+            }
+            p if p == IDCANCEL.0 => {
+                let _ = EndDialog(dialog, IDCANCEL.0 as isize);
+                return true.into();
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+
+    false.into()
+}
+
 pub unsafe extern "system" fn litex_setup_dialog(
     dialog: HWND,
     msg: u32,
     param_1: WPARAM,
-    _param_2: LPARAM,
+    param_2: LPARAM,
 ) -> isize {
     match msg {
         WM_INITDIALOG => {
@@ -64,28 +1420,36 @@ pub unsafe extern "system" fn litex_setup_dialog(
             // * Center Window
             // * SendMessage(EM_SETLIMITTEXT);
 
-            // Restore existing values.
-            let (maybe_file, addr, active) = TTX_LITEX_STATE
-                .with_borrow(|s| (s.filename.clone(), s.addr, s.activity != Activity::Inactive));
+            // Restore existing values. If a different board was last used
+            // on this serial port, offer that one instead.
+            let (current, active, edit) = TTX_LITEX_STATE.with_borrow(|s| {
+                let current = board::com_port(s)
+                    .and_then(|port| board::last_used(s, port))
+                    .filter(|b| Some(&b.name) != s.board.as_ref())
+                    .cloned()
+                    .unwrap_or_else(|| Board::from_state(s.board.as_deref().unwrap_or(""), s));
+                let selected = s.boards.iter().position(|b| b.name == current.name);
 
-            if let Some(file) = maybe_file {
-                let mut file_vec: Vec<u16> = file.as_os_str().encode_wide().collect();
-                file_vec.push(0);
+                let edit = BoardEdit {
+                    boards: s.boards.clone(),
+                    port_boards: s.port_boards.clone(),
+                    selected,
+                };
+                PROTOCOLS.set(s.protocols.clone());
+                fill_protocol_combo(dialog);
 
-                let _ = SetDlgItemTextW(dialog, IDC_LITEX_KERNEL as i32, PCWSTR(file_vec.as_ptr()));
-            }
-
-            let mut addr_str = String::new();
-            let _ = write!(&mut addr_str, "{:#08x}", addr);
-            let addr_os: OsString = addr_str.into();
-            let mut addr_vec: Vec<u16> = addr_os.encode_wide().collect();
-            addr_vec.push(0);
+                show_history(dialog, &s.history);
+                show_options(dialog, &s.options);
+                (current, s.activity.in_transfer() || s.armed(), edit)
+            });
 
-            let _ = SetDlgItemTextW(
-                dialog,
-                IDC_LITEX_BOOT_ADDR as i32,
-                PCWSTR(addr_vec.as_ptr()),
-            );
+            show_board(dialog, &current);
+            if let Some(file) = &current.filename {
+                show_project_config(dialog, file);
+            }
+            show_summary(dialog, None);
+            fill_board_combo(dialog, &edit);
+            BOARD_EDIT.set(edit);
 
             let _ = SendDlgItemMessageW(
                 dialog,
@@ -94,8 +1458,28 @@ pub unsafe extern "system" fn litex_setup_dialog(
                 WPARAM(active.into()),
                 LPARAM(0),
             );
+
+            show_transfer_status(dialog);
+            SetTimer(Some(dialog), STATUS_TIMER_ID, STATUS_INTERVAL_MS, None);
+
             return true.into();
         }
+        WM_TIMER if param_1.0 == STATUS_TIMER_ID => {
+            show_transfer_status(dialog);
+            return true.into();
+        }
+        WM_DESTROY => {
+            let _ = KillTimer(Some(dialog), STATUS_TIMER_ID);
+        }
+        WM_CTLCOLORSTATIC => {
+            let label = GetDlgCtrlID(HWND(param_2.0 as *mut c_void));
+            if ERROR_LABELS.iter().any(|l| *l as i32 == label) {
+                let hdc = HDC(param_1.0 as *mut c_void);
+                SetTextColor(hdc, COLORREF(0x0000_00C0));
+                SetBkMode(hdc, TRANSPARENT);
+                return GetSysColorBrush(COLOR_BTNFACE).0 as isize;
+            }
+        }
         WM_COMMAND => match param_1.0 as i32 {
             p if p == IDOK.0 => {
                 trace!(target: "setup_dialog", "OK");
@@ -108,40 +1492,76 @@ pub unsafe extern "system" fn litex_setup_dialog(
                     LPARAM(0),
                 )
                 .0 != 0;
+                debug!(target: "setup_dialog", "Active: {:?}", active);
 
-                // TODO: If both are clear, Windows returns "Handle is invalid" for both.
-                // If only path is clear, Windows returns "Handle is invalid" for path.
-                // If only address is clear, Windows returns empty string for address.
-                // Why?
-                let kernel_path = get_dlg_osstring(dialog, IDC_LITEX_KERNEL as i32)
-                    .map_err(Error::WinError)
-                    .map(|kpath| PathBuf::from(kpath));
-
-                let boot_addr = get_dlg_osstring(dialog, IDC_LITEX_BOOT_ADDR as i32)
-                    .map_err(Error::WinError)
-                    .and_then(|os| {
-                        let boot_str = os.to_string_lossy().into_owned();
-                        parse::<u32>(&boot_str).map_err(|_| Error::BadAddressError(boot_str))
-                    });
+                let settings = DlgSettings::read(dialog);
+                let problems = settings.validate(active);
+                problems.show(dialog);
+                if !problems.is_empty() {
+                    debug!(target: "setup_dialog", "Refusing to close: {:?}", problems);
+                    return true.into();
+                }
 
-                debug!(target: "setup_dialog", "Kernel Path: {:?}", kernel_path);
-                debug!(target: "setup_dialog", "Boot Address: {:?}", boot_addr);
-                debug!(target: "setup_dialog", "Active: {:?}", active);
+                // Changing settings under a running upload would corrupt
+                // it, so only do that if the user agrees to abort it.
+                let (busy, changed) = TTX_LITEX_STATE.with_borrow(|s| {
+                    let changed = Board::from_state("", s) != settings.to_board("")
+                        || settings.options.as_ref().ok() != Some(&s.options)
+                        || !active;
+                    (s.activity.in_transfer(), changed)
+                });
+                if busy && changed && !confirm_abort(dialog) {
+                    return true.into();
+                }
+
+                let mut edit = BOARD_EDIT.take();
+                let protocols = PROTOCOLS.take();
+                let board_name = edit.selected.map(|i| edit.boards[i].name.clone());
+                if let Some(i) = edit.selected {
+                    edit.boards[i] = settings.to_board(&edit.boards[i].name);
+                }
 
                 TTX_LITEX_STATE.with_borrow_mut(|s| {
-                    s.filename = kernel_path.ok();
-                    s.addr = boot_addr.as_ref().copied().unwrap_or(0x40000000);
+                    if busy && changed {
+                        let msg = super::io::abort_transfer(s, "cancelled from the LiteX dialog.");
+                        s.notices.push_back(msg.into());
+                    }
+                    if !busy || changed {
+                        settings.to_board("").apply(s);
+                        if let Ok(options) = &settings.options {
+                            s.options = options.clone();
+                        }
+                    }
 
-                    if s.filename.is_some() && boot_addr.is_ok() && active {
-                        s.matcher.reset();
-                        s.activity = Activity::LookForMagic;
-                        s.last_frame_acked = None;
-                        s.last_frame_sent = None;
+                    if let Some(port) = board::com_port(s) {
+                        match &board_name {
+                            Some(name) => edit.port_boards.insert(port, name.clone()),
+                            None => edit.port_boards.remove(&port),
+                        };
+                    }
+                    s.protocols = protocols;
+                    s.boards = edit.boards;
+                    s.port_boards = edit.port_boards;
+                    s.board = board_name;
+
+                    if let (Some(file), &Ok(addr)) = (&settings.kernel_path, &settings.boot_addr) {
+                        s.history.push_file(file);
+                        s.history.push_addr(addr);
+                    }
+                    if let Err(e) = setup::save_history(s) {
+                        error!(target: "setup_dialog", "Could not save history: {}", e);
+                    }
+
+                    if busy && !changed {
+                        // Leave the running upload alone.
+                    } else if s.filename.is_some() && active {
+                        s.arm();
 
                         info!(target: "setup_dialog", "Plugin now actively searching for magic string.");
                     } else {
-                        s.activity = Activity::Inactive;
+                        s.disarm();
                     }
+                    update_watch(s);
                 });
 
                 let _ = EndDialog(dialog, IDOK.0 as isize);
@@ -154,14 +1574,254 @@ pub unsafe extern "system" fn litex_setup_dialog(
             }
             p if p == IDC_LITEX_CHOOSE_KERNEL_BUTTON as i32 => {
                 trace!(target: "setup_dialog", "Choose Kernel");
-                if let Some(path) = FileDialog::new().pick_file() {
+                if let Some(path) = pick_kernel_file(dialog) {
                     let widepath = U16CString::from_os_str_truncate(path.as_os_str());
                     if let Err(e) =
                         SetDlgItemTextW(dialog, IDC_LITEX_KERNEL as i32, PCWSTR(widepath.as_ptr()))
                     {
                         error!(target: "setup_dialog", "Could not set kernel file path: {}", e);
                     }
+
+                    if let Some(config) = show_project_config(dialog, &path) {
+                        apply_project_config(dialog, &config);
+                    }
+                    show_summary(dialog, None);
+                }
+            }
+            // Whatever was wrong is being fixed; stop complaining about it.
+            p if (p & 0xFFFF) == IDC_LITEX_KERNEL as i32 && (p >> 16) as u32 == CBN_EDITCHANGE => {
+                let _ = set_dlg_str(dialog, IDC_LITEX_KERNEL_ERR as i32, "");
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_BOOT_ADDR as i32
+                && matches!((p >> 16) as u32, CBN_EDITCHANGE | CBN_SELCHANGE) =>
+            {
+                let _ = set_dlg_str(dialog, IDC_LITEX_BOOT_ADDR_ERR as i32, "");
+            }
+            // Typed-in changes are summarized once the user moves on.
+            p if (p & 0xFFFF) == IDC_LITEX_KERNEL as i32 && (p >> 16) as u32 == CBN_KILLFOCUS => {
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_BOOT_ADDR as i32
+                && (p >> 16) as u32 == CBN_KILLFOCUS =>
+            {
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_MAX_PAYLOAD as i32
+                && (p >> 16) as u32 == EN_KILLFOCUS =>
+            {
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_JUMP_ADDR as i32 && (p >> 16) as u32 == EN_KILLFOCUS => {
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_JUMP_ADDR as i32 && (p >> 16) as u32 == EN_CHANGE => {
+                let _ = set_dlg_str(dialog, IDC_LITEX_JUMP_ADDR_ERR as i32, "");
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_KERNEL as i32 && (p >> 16) as u32 == CBN_SELCHANGE => {
+                let _ = set_dlg_str(dialog, IDC_LITEX_KERNEL_ERR as i32, "");
+                trace!(target: "setup_dialog", "Recent file chosen");
+                if let Some(file) = selected_combo_text(dialog, IDC_LITEX_KERNEL as i32) {
+                    if let Some(config) = show_project_config(dialog, Path::new(&file)) {
+                        apply_project_config(dialog, &config);
+                    }
+                    show_summary(dialog, Some(Path::new(&file)));
+                }
+            }
+            p if p == IDC_LITEX_CHOOSE_CSR_BUTTON as i32 => {
+                trace!(target: "setup_dialog", "Choose csr.json");
+                if let Some(path) = pick_csr_json(dialog) {
+                    let _ = set_dlg_str(dialog, IDC_LITEX_CSR_JSON as i32, &path.to_string_lossy());
+                    show_regions(dialog, selected_region(dialog).as_deref());
+                    show_summary(dialog, None);
+                }
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_CSR_JSON as i32 && (p >> 16) as u32 == EN_CHANGE => {
+                let _ = set_dlg_str(dialog, IDC_LITEX_CSR_ERR as i32, "");
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_CSR_JSON as i32 && (p >> 16) as u32 == EN_KILLFOCUS => {
+                show_regions(dialog, selected_region(dialog).as_deref());
+                show_summary(dialog, None);
+            }
+            // Picking a region means loading at its start.
+            p if (p & 0xFFFF) == IDC_LITEX_REGION as i32 && (p >> 16) as u32 == CBN_SELCHANGE => {
+                trace!(target: "setup_dialog", "Region changed");
+                let region = selected_region(dialog);
+                let base = region.and_then(|name| {
+                    show_regions(dialog, Some(&name))?
+                        .region(&name)
+                        .map(|r| r.base)
+                });
+                if let Some(base) = base {
+                    let _ = set_dlg_str(
+                        dialog,
+                        IDC_LITEX_BOOT_ADDR as i32,
+                        &format!("{:#08x}", base),
+                    );
+                    let _ = set_dlg_str(dialog, IDC_LITEX_BOOT_ADDR_ERR as i32, "");
                 }
+                show_summary(dialog, None);
+            }
+            p if p == IDC_LITEX_CHUNK_AUTO as i32 => {
+                let auto = get_dlg_check(dialog, IDC_LITEX_CHUNK_AUTO as i32);
+                enable_dlg_item(dialog, IDC_LITEX_CHUNK_SIZE as i32, !auto);
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_ARM_MODE as i32 && (p >> 16) as u32 == CBN_SELCHANGE => {
+                let times = selected_index(dialog, IDC_LITEX_ARM_MODE as i32) == Some(2);
+                enable_dlg_item(dialog, IDC_LITEX_ARM_COUNT as i32, times);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_CHUNK_SIZE as i32
+                && (p >> 16) as u32 == EN_KILLFOCUS =>
+            {
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_PROTOCOL as i32 && (p >> 16) as u32 == CBN_SELCHANGE => {
+                trace!(target: "setup_dialog", "Protocol changed");
+                if let Some(protocol) = selected_protocol_name(dialog)
+                    .and_then(|n| PROTOCOLS.with_borrow(|user| Profile::find(&n, user)))
+                {
+                    if protocol.is_custom() {
+                        // Keep whatever is shown so it can be tweaked.
+                        for control in [
+                            IDC_LITEX_MAGIC,
+                            IDC_LITEX_MAGIC_RESPONSE,
+                            IDC_LITEX_MAX_PAYLOAD,
+                            IDC_LITEX_ALLOW_JUMP,
+                        ] {
+                            enable_dlg_item(dialog, control as i32, true);
+                        }
+                        enable_dlg_item(dialog, IDC_LITEX_PROTOCOL_DELETE as i32, false);
+                    } else {
+                        show_protocol(dialog, &protocol);
+                    }
+                }
+                show_summary(dialog, None);
+            }
+            p if (p & 0xFFFF) == IDC_LITEX_BOARD as i32 && (p >> 16) as u32 == CBN_SELCHANGE => {
+                trace!(target: "setup_dialog", "Board changed");
+                let settings = DlgSettings::read(dialog);
+
+                BOARD_EDIT.with_borrow_mut(|edit| {
+                    // Keep edits to the board we're switching away from.
+                    if let Some(i) = edit.selected {
+                        edit.boards[i] = settings.to_board(&edit.boards[i].name);
+                    }
+
+                    edit.selected = selected_board(dialog);
+                    if let Some(i) = edit.selected {
+                        show_board(dialog, &edit.boards[i]);
+                        let file = edit.boards[i].filename.clone().unwrap_or_default();
+                        show_project_config(dialog, &file);
+                    }
+                    fill_board_combo(dialog, edit);
+                });
+                show_summary(dialog, None);
+            }
+            p if p == IDC_LITEX_BOARD_NEW as i32 => {
+                trace!(target: "setup_dialog", "New board");
+                let mut edit = BOARD_EDIT.take();
+                let initial = format!("Board {}", edit.boards.len() + 1);
+
+                if let Some(name) = prompt_name(dialog, u16cstr!("Board Name"), &initial)
+                    .and_then(|n| check_board_name(dialog, &edit, &n, None))
+                {
+                    let settings = DlgSettings::read(dialog);
+                    if let Some(i) = edit.selected {
+                        edit.boards[i] = settings.to_board(&edit.boards[i].name);
+                    }
+
+                    edit.boards.push(settings.to_board(&name));
+                    edit.selected = Some(edit.boards.len() - 1);
+                    fill_board_combo(dialog, &edit);
+                }
+
+                BOARD_EDIT.set(edit);
+            }
+            p if p == IDC_LITEX_BOARD_RENAME as i32 => {
+                trace!(target: "setup_dialog", "Rename board");
+                let mut edit = BOARD_EDIT.take();
+
+                if let Some(i) = edit.selected {
+                    let old = edit.boards[i].name.clone();
+                    if let Some(name) = prompt_name(dialog, u16cstr!("Board Name"), &old)
+                        .and_then(|n| check_board_name(dialog, &edit, &n, Some(i)))
+                    {
+                        for port_board in edit.port_boards.values_mut() {
+                            if *port_board == old {
+                                *port_board = name.clone();
+                            }
+                        }
+
+                        edit.boards[i].name = name;
+                        fill_board_combo(dialog, &edit);
+                    }
+                }
+
+                BOARD_EDIT.set(edit);
+            }
+            p if p == IDC_LITEX_PROTOCOL_SAVE as i32 => {
+                trace!(target: "setup_dialog", "Save protocol");
+                let protocol = match get_dlg_protocol(dialog) {
+                    Ok(protocol) => protocol,
+                    Err(e) => {
+                        let _ = set_dlg_str(dialog, IDC_LITEX_PROTOCOL_ERR as i32, &sentence(&e));
+                        return true.into();
+                    }
+                };
+
+                let initial = if protocol.is_builtin() {
+                    ""
+                } else {
+                    &protocol.name
+                };
+                if let Some(name) = prompt_name(dialog, u16cstr!("Protocol Name"), initial)
+                    .and_then(|n| check_protocol_name(dialog, &n))
+                {
+                    let protocol = Profile { name, ..protocol };
+                    PROTOCOLS.with_borrow_mut(|user| {
+                        match user.iter_mut().find(|p| p.name == protocol.name) {
+                            Some(p) => *p = protocol.clone(),
+                            None => user.push(protocol.clone()),
+                        }
+                    });
+                    fill_protocol_combo(dialog);
+                    select_protocol(dialog, &protocol);
+                    show_summary(dialog, None);
+                }
+            }
+            p if p == IDC_LITEX_PROTOCOL_DELETE as i32 => {
+                trace!(target: "setup_dialog", "Delete protocol");
+                if let Some(old) = get_dlg_protocol(dialog).ok().filter(|p| !p.is_builtin()) {
+                    PROTOCOLS.with_borrow_mut(|user| user.retain(|p| p.name != old.name));
+
+                    // Boards that used it keep its settings as custom ones.
+                    let custom = Profile {
+                        name: Profile::CUSTOM.to_string(),
+                        ..old.clone()
+                    };
+                    BOARD_EDIT.with_borrow_mut(|edit| {
+                        for b in &mut edit.boards {
+                            if b.protocol.name == old.name {
+                                b.protocol = custom.clone();
+                            }
+                        }
+                    });
+
+                    fill_protocol_combo(dialog);
+                    select_protocol(dialog, &custom);
+                    show_summary(dialog, None);
+                }
+            }
+            p if p == IDC_LITEX_BOARD_DELETE as i32 => {
+                trace!(target: "setup_dialog", "Delete board");
+                BOARD_EDIT.with_borrow_mut(|edit| {
+                    // The settings stay in the dialog; only the name goes.
+                    if let Some(i) = edit.selected.take() {
+                        let old = edit.boards.remove(i);
+                        edit.port_boards.retain(|_, name| *name != old.name);
+                        fill_board_combo(dialog, edit);
+                    }
+                });
             }
             _ => {}
         },
@@ -184,6 +1844,22 @@ ttx_export! {
             ID_MENU_LITEX,
             PCWSTR(u16cstr!("LiteX").as_ptr()),
         );
+        let _ = AppendMenuW(
+            transfer_menu,
+            MF_ENABLED | MF_STRING,
+            ID_MENU_LITEX_UPLOAD_NOW,
+            PCWSTR(u16cstr!("LiteX Upload Now").as_ptr()),
+        );
+    }
+}
+
+ttx_export! {
+    pub unsafe fn ttx_modify_popup_menu(menu: HMENU) {
+        // Check the "LiteX" item while armed. Does nothing for the menus
+        // that don't contain it.
+        let armed = TTX_LITEX_STATE.with_borrow(|s| s.armed());
+        let check = if armed { MF_CHECKED } else { MF_UNCHECKED };
+        CheckMenuItem(menu, ID_MENU_LITEX as u32, (MF_BYCOMMAND | check).0);
     }
 }
 
@@ -207,6 +1883,23 @@ ttx_export! {
 
                 return 1;
             }
+            ID_MENU_LITEX_UPLOAD_NOW => {
+                debug!(target: "TTXProcessCommand", "LiteX Upload Now clicked.");
+
+                TTX_LITEX_STATE.with_borrow_mut(|s| {
+                    // Asked for by hand, so don't ask again.
+                    match upload_now(s) {
+                        Ok(true) => s.upload_approved = true,
+                        Ok(false) => {}
+                        Err(e) => {
+                            error!(target: "TTXProcessCommand", "Could not send trigger: {}", e);
+                            notice(s, MessageLevel::Summary, e);
+                        }
+                    }
+                });
+
+                return 1;
+            }
             _ => {
                 return 0;
             }
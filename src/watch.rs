@@ -0,0 +1,190 @@
+/*! Watching the firmware file for new builds.
+
+With "Re-upload when file changes" checked, the plugin polls the chosen file,
+and once a rebuild has finished writing it, sends the "Upload Now" trigger so
+the board asks for the new build. A file counts as finished once its size and
+modification time have stopped changing for [`SETTLE_TIME`], so a
+half-written file is never sent. */
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Local};
+use sha2::{Digest, Sha256};
+
+/// How often to look at the file.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the file must stay unchanged before it's uploaded.
+pub const SETTLE_TIME: Duration = Duration::from_millis(1500);
+
+/// Enough of a file's metadata to tell builds apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub modified: SystemTime,
+    pub len: u64,
+}
+
+impl Stamp {
+    /// `None` if the file is missing, which it may be mid-rebuild.
+    pub fn of(path: &Path) -> Option<Self> {
+        let meta = path.metadata().ok()?;
+        Some(Self {
+            modified: meta.modified().ok()?,
+            len: meta.len(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FileWatch {
+    path: Option<PathBuf>,
+    /// The build last uploaded, or present when watching started.
+    loaded: Option<Stamp>,
+    /// What the file looked like at the last poll, and since when.
+    seen: Option<(Stamp, Instant)>,
+}
+
+impl FileWatch {
+    /// Watch `path`, whose current contents don't need uploading. Does
+    /// nothing if `path` is already being watched.
+    pub fn watch(&mut self, path: &Path, current: Option<Stamp>) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+
+        *self = Self {
+            path: Some(path.to_path_buf()),
+            loaded: current,
+            seen: None,
+        };
+    }
+
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Note what the file looks like `now`. Returns true, once, for each new
+    /// build that has settled.
+    pub fn poll(&mut self, stamp: Option<Stamp>, now: Instant) -> bool {
+        // Missing or truncated: a rebuild is underway.
+        let Some(stamp) = stamp.filter(|s| s.len != 0) else {
+            self.seen = None;
+            return false;
+        };
+
+        match self.seen {
+            Some((seen, since)) if seen == stamp => {
+                if now - since < SETTLE_TIME || self.loaded == Some(stamp) {
+                    return false;
+                }
+
+                self.loaded = Some(stamp);
+                true
+            }
+            _ => {
+                self.seen = Some((stamp, now));
+                false
+            }
+        }
+    }
+}
+
+/// Which build `path` holds, e.g. `demo.bin, modified 2025-02-18 12:00:00,
+/// SHA-256 1a2b3c4d5e6f7081`.
+pub fn describe_build(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let modified = file.metadata()?.modified().ok();
+
+    let mut sha = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha.update(&buf[..n]);
+    }
+
+    let mut out = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if let Some(modified) = modified {
+        let modified: DateTime<Local> = modified.into();
+        let _ = write!(out, ", modified {}", modified.format("%Y-%m-%d %H:%M:%S"));
+    }
+    out.push_str(", SHA-256 ");
+    for b in &sha.finalize()[..8] {
+        let _ = write!(out, "{:02x}", b);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(secs: u64, len: u64) -> Option<Stamp> {
+        Some(Stamp {
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            len,
+        })
+    }
+
+    fn watching() -> (FileWatch, Instant) {
+        let mut watch = FileWatch::default();
+        watch.watch(Path::new("demo.bin"), stamp(1, 100));
+        (watch, Instant::now())
+    }
+
+    #[test]
+    fn unchanged_file_isnt_uploaded() {
+        let (mut watch, t0) = watching();
+        assert!(!watch.poll(stamp(1, 100), t0));
+        assert!(!watch.poll(stamp(1, 100), t0 + SETTLE_TIME * 2));
+    }
+
+    #[test]
+    fn new_build_is_uploaded_once_settled() {
+        let (mut watch, t0) = watching();
+        assert!(!watch.poll(stamp(2, 200), t0));
+        assert!(!watch.poll(stamp(2, 200), t0 + SETTLE_TIME / 2));
+        assert!(watch.poll(stamp(2, 200), t0 + SETTLE_TIME));
+        assert!(!watch.poll(stamp(2, 200), t0 + SETTLE_TIME * 2));
+    }
+
+    #[test]
+    fn still_growing_file_waits() {
+        let (mut watch, t0) = watching();
+        assert!(!watch.poll(stamp(2, 50), t0));
+        assert!(!watch.poll(stamp(3, 150), t0 + SETTLE_TIME));
+        assert!(!watch.poll(stamp(3, 150), t0 + SETTLE_TIME * 3 / 2));
+        assert!(watch.poll(stamp(3, 150), t0 + SETTLE_TIME * 2));
+    }
+
+    #[test]
+    fn missing_or_empty_file_waits() {
+        let (mut watch, t0) = watching();
+        assert!(!watch.poll(None, t0));
+        assert!(!watch.poll(stamp(2, 0), t0 + SETTLE_TIME));
+        assert!(!watch.poll(stamp(2, 0), t0 + SETTLE_TIME * 2));
+        assert!(!watch.poll(stamp(3, 200), t0 + SETTLE_TIME * 3));
+        assert!(watch.poll(stamp(3, 200), t0 + SETTLE_TIME * 4));
+    }
+
+    #[test]
+    fn rewatching_same_path_keeps_state() {
+        let (mut watch, t0) = watching();
+        assert!(!watch.poll(stamp(2, 200), t0));
+        watch.watch(Path::new("demo.bin"), stamp(2, 200));
+        assert!(watch.poll(stamp(2, 200), t0 + SETTLE_TIME));
+    }
+}